@@ -2,12 +2,16 @@ use core::{num::NonZeroU64, mem::size_of, cmp::Ordering};
 
 use alloc::{string::String,vec::Vec};
 
-use bytemuck::Zeroable;
-use nonzero_ext::nonzero;
+use bytemuck::{Pod, Zeroable};
 
-use crate::{object::{RootDescriptor,consts, PhantomFSMagic, FSRequiredFeatures, FSOptionalFeatures, ObjectId, Object, SectorPos, AbsPos, ObjectType, ObjectFlags, StreamListing, StreamFlags, VolumeSpan, StreamId, DirectoryElement}, io::{Read, Seek, Write, SeekPos}, uuid::Uuid};
+use crate::{object::{RootDescriptor,consts, PhantomFSMagic, FSRequiredFeatures, FSOptionalFeatures, ObjectId, Object, SectorPos, AbsPos, ObjectType, ObjectFlags, StreamListing, StreamFlags, VolumeSpan, StreamId, DirectoryElement, DirectoryElementFlags, SecurityDescriptorRow, SecurityDescRowMode, SecurityDescRowFlags, DeviceId, LegacyDeviceNumber, LegacySecurityDescriptor}, io::{Read, Seek, Write, SeekPos}, uuid::Uuid};
 use crate::helpers::extend_str;
 
+enum Located{
+    Found{sector: u128, offset: u64, avail: u64},
+    Skipped(u64),
+}
+
 pub struct FilesystemAccess<S>{
     stream: S,
     root_desc: Option<RootDescriptor>,
@@ -20,10 +24,39 @@ impl<S> FilesystemAccess<S>{
     }
 }
 
+/// The precedence of the mode of a row which decides an access check. Forbid rows take precedence over Deny rows, which take precedence over Permit rows.
+fn mode_precedence(mode: SecurityDescRowMode) -> u8{
+    match mode{
+        SecurityDescRowMode::Permit => 1,
+        SecurityDescRowMode::Deny => 2,
+        SecurityDescRowMode::Forbid => 3,
+        _ => 0,
+    }
+}
+
+/// Checks whether a row can be interpreted. A row with an unknown mode can only be interpreted if it does not have the `REQUIRED` flag, in which case it is ignored.
+fn row_is_understood(row: &SecurityDescriptorRow) -> bool{
+    matches!(row.flags_and_mode.mode(), SecurityDescRowMode::Permit | SecurityDescRowMode::Deny | SecurityDescRowMode::Forbid | SecurityDescRowMode::Inherit)
+        || !row.flags_and_mode.contains(SecurityDescRowFlags::REQUIRED)
+}
+
+/// Checks whether `name` can be the name of a directory entry. Names must be nonempty and must not contain a `/` or null byte.
+/// `.` and `..` are also rejected, since path resolution never looks them up.
+fn is_valid_entry_name(name: &str) -> bool{
+    !name.is_empty() && name!="." && name!=".." && !name.as_bytes().iter().any(|b| *b==0 || *b==b'/')
+}
+
+/// Computes the checksum of `desc`, which covers every byte of the descriptor before the `crc` field.
+fn descriptor_crc(desc: &RootDescriptor) -> u32{
+    let crc_offset = (&desc.crc as *const u32 as usize) - (desc as *const RootDescriptor as usize);
+
+    crc::Crc::<u32>::new(&crc::CRC_32_CKSUM).checksum(&bytemuck::bytes_of(desc)[..crc_offset])
+}
+
 impl<S: Write + Seek> FilesystemAccess<S>{
     pub fn sync(&mut self) -> crate::io::Result<()>{
-        let pos = self.stream.stream_position()?;
-        if let Some(desc) = self.root_desc.as_ref(){
+        if let Some(desc) = self.root_desc.as_mut(){
+            desc.crc = descriptor_crc(desc);
             self.stream.seek(crate::io::SeekPos::StartSector(1))?;
             self.stream.write_all(bytemuck::bytes_of(desc))?;
         }
@@ -34,427 +67,3505 @@ impl<S: Write + Seek> FilesystemAccess<S>{
 }
 
 impl<S: Read + Write + Seek> FilesystemAccess<S>{
-    pub fn create_object(&mut self, init_size: u64, ty: ObjectType, init_string_tab: &str,owner_uuid: Uuid) -> crate::io::Result<ObjectId>{
-        let desc = self.get_or_read_descriptor()?;
+    fn write_obj(&mut self, id: ObjectId, obj: &Object) -> crate::io::Result<()>{
+        let objtab_end = self.get_or_read_descriptor()?.objtab_end;
+
+        self.stream.seek(SeekPos::StartSector(objtab_end.0))?;
+        self.stream.seek(SeekPos::Curr(-((id.0.get()*(size_of::<Object>() as u64)) as i64)))?;
+        self.stream.write_all(bytemuck::bytes_of(obj))
+    }
 
-        let objtab_pos = desc.objtab_end;
-        let objtab_size = desc.objtab_size;
+    /// Writes the initial content of a new stream, storing it inline if it fits into [`StreamListing::inline_data`], and in a newly allocated extent otherwise.
+    ///
+    /// The stream is `size` bytes long, with any part not covered by `data` filled with zeroes.
+    fn write_initial_stream(&mut self, listing: &mut StreamListing, data: &[u8], size: u64) -> crate::io::Result<()>{
+        listing.size = size;
 
-        self.stream.seek(SeekPos::StartSector(objtab_pos.0))?;
+        if size<=(listing.inline_data.len() as u64){
+            listing.inline_data[..data.len()].copy_from_slice(data);
+        }else{
+            let base = self.allocate_contiguous_space(size)?;
+            let sectors = (size>>10) + ((size&1023)!=0) as u64;
 
-        for i in 1..=(objtab_size/(size_of::<Object>() as u64)){
-            self.stream.seek(SeekPos::Curr(-(size_of::<Object>() as i64)))?;
-            let mut obj = Object{..Zeroable::zeroed()};
-            self.stream.read_fully(bytemuck::bytes_of_mut(&mut obj))?;
-            self.stream.seek(SeekPos::Curr(-(size_of::<Object>() as i64)))?;
+            self.stream.seek(SeekPos::StartSector(base.0))?;
+            self.stream.write_all(data)?;
+            self.stream.write_zeroes(((sectors<<10) - (data.len() as u64)) as usize)?;
 
-            if obj.weak_ref==0{
-                // new object
-                obj.weak_ref = 1;
-                obj.strong_ref = 1;
-                
-                let mut streams: [StreamListing;16] = Zeroable::zeroed();
+            listing.content_ref = base.0;
+            listing.flags |= StreamFlags::indirection(1);
+        }
 
-                [consts::STREAMS_STREAM, consts::STRINGS_STREAM, consts::SECURITYDESCRIPTOR_STREAM]
-                .into_iter()
-                .zip(&mut streams)
-                .for_each(|(stream, slot)| *slot = StreamListing{name: extend_str(stream), name_ref: None, flags: StreamFlags::REQUIRED, ..Zeroable::zeroed()});
+        Ok(())
+    }
 
-                let pos = self.stream.stream_position()?;
-                let streams_base = self.allocate_contiguous_space(2048)?;
+    /// Writes `listing` back into the Streams stream of `objid`. The listing for the Streams stream itself is also mirrored into the object.
+    fn write_stream_listing(&mut self, objid: ObjectId, id: StreamId, listing: &StreamListing) -> crate::io::Result<()>{
+        let mut obj = self.get_obj_by_id(objid)?;
 
-                streams[0].content_ref = streams_base.0;
-                streams[0].size = 2048;
-                streams[0].flags |= StreamFlags::indirection(1);
+        let pos = id.0*(size_of::<StreamListing>() as u64);
 
-                obj.streams_indirection = 1;
-                obj.streams_ref = streams_base.0;
-                obj.streams_size = 2048;
+        if id==StreamId::STREAMS{
+            obj.streams_ref = listing.content_ref;
+            obj.streams_size = listing.size;
+            obj.streams_indirection = listing.flags.get_indirection() as u8;
+            self.write_obj(objid, &obj)?;
+        }
 
-                self.stream.seek(SeekPos::StartSector(streams_base.0))?;
-                // safety: This is known to never overflow, and i starts at `1`
-                return Ok(ObjectId(unsafe{NonZeroU64::new_unchecked(i)}))
-            }
+        if pos>=obj.streams_size{
+            return Err(crate::io::Error::NotFound)
         }
 
-        todo!("grow object table");
+        self.write_fully_by_indirection(pos, bytemuck::bytes_of(listing), obj.streams_ref, obj.streams_indirection, obj.streams_size)
     }
 
-    pub fn allocate_contiguous_space(&mut self, size: u64) -> crate::io::Result<SectorPos>{
-        todo!("allocate_contiguous_space")
+    fn write_by_indirection(&mut self, offset: u64, buf: &[u8], baseref: u128, indirection: u8, len: u64) -> crate::io::Result<usize>{
+        match self.locate_by_indirection(offset, baseref, indirection, len)?{
+            Some((sector, inner, avail)) => {
+                let len = (buf.len() as u64).min(avail) as usize;
+
+                self.stream.seek(SeekPos::StartSector(sector))?;
+                self.stream.seek(SeekPos::Curr(inner as i64))?;
+                self.stream.write(&buf[..len])
+            }
+            None => Ok(0)
+        }
     }
-    pub fn create_filesystem(&mut self, label: &str, id: Uuid, volsize: u128) -> crate::io::Result<()>{
-        let mut desc = RootDescriptor{
-            magic: PhantomFSMagic::MAGIC,
-            version_major: consts::VERSION_MAJOR,
-            version_minor: consts::VERSION_MAJOR,
-            required_features: FSRequiredFeatures::empty(),
-            optional_features: FSOptionalFeatures::empty(),
-            volume_id_hi: id.hi,
-            volume_id_lo: id.lo,
-            root_object_id: Some(ObjectId(nonzero!(1u64))),
-            objtab_end: SectorPos(volsize),
-            objtab_size: 1024,
-            alloc_tab_begin: AbsPos(2048),
-            alloc_tab_size: 1024, // for now
-            label_ref: None,
-            label: Zeroable::zeroed(),
-            header_size: core::mem::size_of::<RootDescriptor>() as u32,
-            crc: 0,
-        };
 
+    fn write_fully_by_indirection(&mut self, mut offset: u64, mut buf: &[u8], baseref: u128, indirection: u8, len: u64) -> crate::io::Result<()>{
+        if indirection==0{
+            panic!("write_by_indirection does not support inline data, handle via appropriate top-level type instead")
+        }
 
-        self.stream.seek(crate::io::SeekPos::StartSector(volsize))?;
-        self.stream.seek(crate::io::SeekPos::Curr(-1024))?;
+        while !buf.is_empty(){
+            match self.write_by_indirection(offset, buf, baseref, indirection, len){
+                Ok(0) => return Err(crate::io::Error::UnexpectedEof),
+                Ok(n) => {
+                    buf = &buf[n..];
+                    offset += n as u64;
+                }
+                Err(crate::io::Error::Interrupted) => {}
+                Err(e) => return Err(e)
+            }
+        }
+        Ok(())
+    }
 
-        self.stream.write_zeroes(1024)?;
+    fn write_span(&mut self, block: u128, idx: u64, span: &VolumeSpan) -> crate::io::Result<()>{
+        self.stream.seek(SeekPos::StartSector(block))?;
+        self.stream.seek(SeekPos::Curr((idx*(size_of::<VolumeSpan>() as u64)) as i64))?;
+        self.stream.write_all(bytemuck::bytes_of(span))
+    }
 
-        self.stream.seek(crate::io::SeekPos::StartSector(2))?;
-        let mut init_reserve = VolumeSpan{
-            base_sector: 0,
-            extent: 8,
-            ..Zeroable::zeroed()
-        };
-        self.stream.write_all(bytemuck::bytes_of(&init_reserve))?;
-        self.stream.write_zeroes(1024-32)?;
-        
-        
-        self.root_desc = Some(desc);
-        
+    /// Returns the number of used entries in the index block at `block`, which is `sectors` long.
+    fn count_spans(&mut self, block: u128, sectors: u64) -> crate::io::Result<u64>{
+        let capacity = sectors*((1024/size_of::<VolumeSpan>()) as u64);
 
-        Ok(())
+        for idx in 0..capacity{
+            if self.read_span(block, idx)?.extent==0{
+                return Ok(idx)
+            }
+        }
+
+        Ok(capacity)
     }
-}
 
-impl<S: Read + Seek> FilesystemAccess<S>{
-    pub fn get_or_read_descriptor(&mut self) -> crate::io::Result<&mut RootDescriptor>{
-        if let Some(desc) = self.root_desc.as_mut(){
-            Ok(unsafe{&mut *(desc as *mut RootDescriptor)}) // Hecking NLL get_or_insert_with
+    /// Returns the position (index block and entry) and value of the last data extent in the index block at `block`.
+    fn last_leaf(&mut self, block: u128, sectors: u64, level: u8) -> crate::io::Result<Option<(u128, u64, VolumeSpan)>>{
+        let count = self.count_spans(block, sectors)?;
+
+        if count==0{
+            return Ok(None)
+        }
+
+        let span = self.read_span(block, count-1)?;
+
+        if level==2{
+            Ok(Some((block, count-1, span)))
         }else{
+            self.last_leaf(span.base_sector, span.extent, level-1)
+        }
+    }
 
-            let mut root_desc: RootDescriptor = Zeroable::zeroed();
+    /// Allocates a new single sector index block, containing only `entry`.
+    fn new_index_block(&mut self, entry: &VolumeSpan) -> crate::io::Result<u128>{
+        let block = self.allocate_contiguous_space(1024)?;
 
-            let pos = self.stream.stream_position()?;
+        self.stream.seek(SeekPos::StartSector(block.0))?;
+        self.stream.write_all(bytemuck::bytes_of(entry))?;
+        self.stream.write_zeroes(1024-size_of::<VolumeSpan>())?;
 
-            self.stream.seek(crate::io::SeekPos::StartSector(1))?;
+        Ok(block.0)
+    }
 
-            self.stream.read_fully(bytemuck::bytes_of_mut(&mut root_desc))?;
+    /// Allocates a new single sector index block with `level` levels of indirection, containing only the path to the data extent `leaf`.
+    fn build_index_chain(&mut self, level: u8, leaf: VolumeSpan) -> crate::io::Result<VolumeSpan>{
+        let entry = if level==2{
+            leaf
+        }else{
+            self.build_index_chain(level-1, leaf)?
+        };
 
+        let block = self.new_index_block(&entry)?;
 
-            if root_desc.magic!=PhantomFSMagic::MAGIC{
-                return Err(crate::io::Error::InvalidData);
-            }
+        Ok(VolumeSpan{base_sector: block, extent: 1, __reserved: 0})
+    }
 
-            if root_desc.version_major!=consts::VERSION_MAJOR{
-                return Err(crate::io::Error::InvalidData);
+    /// Appends the data extent `leaf` after the last data extent in the index block at `block`.
+    ///
+    /// Returns `false` if there is no room for it in the tree below `block`.
+    fn append_leaf(&mut self, block: u128, sectors: u64, level: u8, leaf: VolumeSpan) -> crate::io::Result<bool>{
+        let count = self.count_spans(block, sectors)?;
+        let capacity = sectors*((1024/size_of::<VolumeSpan>()) as u64);
+
+        if level!=2 && count!=0{
+            let last = self.read_span(block, count-1)?;
+            if self.append_leaf(last.base_sector, last.extent, level-1, leaf)?{
+                return Ok(true)
             }
+        }
 
-            if root_desc.header_size<(size_of::<RootDescriptor>() as u32){
-                return Err(crate::io::Error::InvalidData);
-            }
+        if count==capacity{
+            return Ok(false)
+        }
 
-            if root_desc.crc != crc::Crc::<u32>::new(&crc::CRC_32_CKSUM).checksum(bytemuck::bytes_of(&root_desc)){
-                return Err(crate::io::Error::InvalidData);
-            }
+        let entry = if level==2{
+            leaf
+        }else{
+            self.build_index_chain(level-1, leaf)?
+        };
 
-            self.root_desc = Some(root_desc);
+        self.write_span(block, count, &entry)?;
 
-            Ok(self.root_desc.as_mut().unwrap())
-        }
+        Ok(true)
     }
 
+    /// Reserves enough space for the content of the stream described by `listing` to hold `new_size` bytes, without changing its layout.
+    ///
+    /// The last extent of the stream is extended in place where possible, otherwise a new extent is appended to the tree.
+    /// Returns `false` if the current layout cannot hold `new_size` bytes.
+    fn try_reserve_stream_content(&mut self, listing: &mut StreamListing, new_size: u64) -> crate::io::Result<bool>{
+        let old_sectors = (listing.size>>10) + ((listing.size&1023)!=0) as u64;
+        let new_sectors = (new_size>>10) + ((new_size&1023)!=0) as u64;
 
-    pub fn get_obj_by_id(&mut self, id: ObjectId) -> crate::io::Result<Object>{
-        let desc = self.get_or_read_descriptor()?;
-        let objtab_end = desc.objtab_end;
-        let objtabsize = desc.objtab_size;
-
-        let pos = id.0.get()*(size_of::<Object>() as u64);
+        let indirection = listing.flags.get_indirection() as u8;
 
-        if pos>objtabsize{
-            return Err(crate::io::Error::NotFound);
+        if indirection==0{
+            return Ok(new_size<=(listing.inline_data.len() as u64))
         }
 
-        self.stream.seek(SeekPos::StartSector(objtab_end.0))?;
-        self.stream.seek(SeekPos::Curr(-(pos as i64)))?;
+        if new_sectors<=old_sectors{
+            return Ok(true)
+        }
 
-        let mut obj: Object = Zeroable::zeroed();
+        let extra = new_sectors-old_sectors;
 
-        self.stream.read_fully(bytemuck::bytes_of_mut(&mut obj))?;
+        if indirection==1{
+            if old_sectors==0{
+                listing.content_ref = self.allocate_contiguous_space(extra<<10)?.0;
+                return Ok(true)
+            }else{
+                return self.try_reserve_at(SectorPos(listing.content_ref+(old_sectors as u128)), extra)
+            }
+        }
 
-        if obj.weak_ref==0{
-            // This is an invalid object, so for all intents and purposes, it does not exist
-            return Err(crate::io::Error::NotFound)
+        if let Some((block, idx, mut last)) = self.last_leaf(listing.content_ref, 1, indirection)?{
+            if self.try_reserve_at(SectorPos(last.base_sector+(last.extent as u128)), extra)?{
+                last.extent += extra;
+                self.write_span(block, idx, &last)?;
+                return Ok(true)
+            }
         }
 
-        Ok(obj)
-    }
+        let base = self.allocate_contiguous_space(extra<<10)?;
+        let leaf = VolumeSpan{base_sector: base.0, extent: extra, __reserved: 0};
 
-    fn read_by_indirection(&mut self, offset: u64,buf: &mut [u8], baseref: u128, indirection: u8,len: u64) -> crate::io::Result<usize>{
-        if indirection==1{
-            self.stream.seek(SeekPos::StartSector(baseref))?;
-            self.stream.seek(SeekPos::Curr(offset as i64))?;
-            self.stream.read(buf)
+        if self.append_leaf(listing.content_ref, 1, indirection, leaf)?{
+            Ok(true)
         }else{
-            let indirection = indirection as usize;
-            let mut stack: [VolumeSpan;16] = [Zeroable::zeroed();16];
-            stack[0] = VolumeSpan{base_sector: baseref, extent: !0, __reserved:0};
-            let mut stackpos = 1;
-            let mut cursizesector = 0u64;
-            let mut offset_at_level = 0u64;
-
-            loop{
-                if cursizesector*1024 > len{
-                    return Ok(0)
-                }
-                
-                if stackpos==indirection{
-                    if (cursizesector+1)*1024 > offset{
-                        let abspos = offset-(cursizesector*1024);
-                        self.stream.seek(SeekPos::StartSector(stack[stackpos].base_sector))?;
-                        self.stream.seek(SeekPos::Curr(abspos as i64))?;
-
-                        let len = ((buf.len() as u64).min(len-offset).min(stack[stackpos].extent*1024-abspos)) as usize;
-
-                        return self.stream.read(&mut buf[..len]);
-                    }else{
-                        cursizesector += stack[stackpos].extent;
-                        offset_at_level = stack[stackpos].__reserved + 1;
-                        stackpos -= 1;
-                        continue;
-                    }
-                }
+            self.free_space(base, extra)?;
+            Ok(false)
+        }
+    }
 
-                if offset_at_level*32 >= stack[stackpos-1].extent*1024{
-                    cursizesector += stack[stackpos].extent;
-                    offset_at_level = stack[stackpos].__reserved + 1;
-                    stackpos -= 1;
-                    continue;
+    /// Moves the content of the stream described by `listing` to the layout with one more level of indirection.
+    ///
+    /// Inline content is moved to a new extent, a direct extent becomes the first entry of a new index block, and the root index block of a tree becomes the first entry of a new root.
+    fn promote_stream_content(&mut self, listing: &mut StreamListing) -> crate::io::Result<()>{
+        let sectors = (listing.size>>10) + ((listing.size&1023)!=0) as u64;
+
+        let indirection = listing.flags.get_indirection();
+
+        match indirection{
+            0 => {
+                listing.content_ref = 0;
+                if sectors!=0{
+                    let base = self.allocate_contiguous_space(listing.size)?;
+                    self.stream.seek(SeekPos::StartSector(base.0))?;
+                    self.stream.write_all(&listing.inline_data[..(listing.size as usize)])?;
+                    self.stream.write_zeroes((1024-listing.size) as usize)?;
+                    listing.content_ref = base.0;
                 }
-
-                self.stream.seek(SeekPos::StartSector(stack[stackpos].base_sector))?;
-                self.stream.seek(SeekPos::Curr((stack[stackpos].__reserved as i64)*32))?;
-
-                self.stream.read_fully(bytemuck::bytes_of_mut(&mut stack[stackpos+1]));
-                stackpos += 1;
-                stack[stackpos].__reserved = offset_at_level;
-                offset_at_level = 0;
+                listing.inline_data = Zeroable::zeroed();
+            }
+            1 => {
+                let entry = if sectors==0{
+                    Zeroable::zeroed()
+                }else{
+                    VolumeSpan{base_sector: listing.content_ref, extent: sectors, __reserved: 0}
+                };
+                listing.content_ref = self.new_index_block(&entry)?;
+            }
+            15 => return Err(crate::io::Error::NoSpace),
+            _ => {
+                listing.content_ref = self.new_index_block(&VolumeSpan{base_sector: listing.content_ref, extent: 1, __reserved: 0})?;
             }
         }
 
+        listing.flags = (listing.flags & !StreamFlags::INDIRECTION_MASK) | StreamFlags::indirection(indirection+1);
+
+        Ok(())
     }
 
-    fn read_fully_by_indirection(&mut self, mut offset: u64,mut buf: &mut [u8], baseref: u128, indirection: u8,len: u64) -> crate::io::Result<()>{
-        if indirection==0{
-            panic!("read_by_indirection does not support inline data, handle via appropriate top-level type instead")
+    /// Reserves enough space for the content of the stream described by `listing` to hold `new_size` bytes.
+    ///
+    /// The stream is promoted to layouts with more levels of indirection until it can hold `new_size` bytes:
+    ///  from inline data to a direct extent once it exceeds [`StreamListing::inline_data`], to a tree once the extent cannot be extended in place, and to a deeper tree once the root index block is full.
+    fn reserve_stream_content(&mut self, listing: &mut StreamListing, new_size: u64) -> crate::io::Result<()>{
+        while !self.try_reserve_stream_content(listing, new_size)?{
+            self.promote_stream_content(listing)?;
         }
 
-        while !buf.is_empty(){
-            match self.read_by_indirection(offset, buf, baseref, indirection,len){
-                Ok(0) => return Err(crate::io::Error::UnexpectedEof),
-                Ok(n) => {
-                    buf = &mut buf[n..];
-                    offset += n as u64;
-                }
-                Err(crate::io::Error::Interrupted) => {}
-                Err(e) => return Err(e)
-             }
-        }
         Ok(())
     }
 
-    fn read_nullstr_by_indirection(&mut self, mut offset: u64, baseref: u128, indirection: u8, len: u64) -> crate::io::Result<String>{
-        let mut str = Vec::new();
-        loop{
-            let mut buf = [0;1024];
-            match self.read_by_indirection(offset, &mut buf, baseref, indirection, len){
-                Ok(0) => return Err(crate::io::Error::UnexpectedEof),
-                Ok(n) => {
-                    let buf = &buf[..n];
-
-                    for b in buf{
-                        if *b==0{
-                            return String::from_utf8(str).map_err(|_|crate::io::Error::InvalidData)
-                        }
-                        str.push(*b);
-                    }
-
-                    offset += n as u64;
+    /// Removes every data extent and index block in the index block at `block` which is past the first `keep` sectors of data, and shortens the extent containing the end.
+    /// Entries which are removed are cleared, and the space they used is added to `released` as by [`FilesystemAccess::demote_stream_content`]. Returns the number of sectors of data which are kept.
+    fn truncate_index_block(&mut self, block: u128, sectors: u64, level: u8, mut keep: u64, released: &mut Vec<(u128, u64, u8)>) -> crate::io::Result<u64>{
+        let count = self.count_spans(block, sectors)?;
+        let mut kept = 0;
+
+        for idx in 0..count{
+            let mut span = self.read_span(block, idx)?;
+
+            if keep==0{
+                released.push((span.base_sector, span.extent, level-1));
+                self.write_span(block, idx, &Zeroable::zeroed())?;
+            }else if level==2{
+                if span.extent>keep{
+                    released.push((span.base_sector+(keep as u128), span.extent-keep, 1));
+                    span.extent = keep;
+                    self.write_span(block, idx, &span)?;
                 }
-                Err(crate::io::Error::Interrupted) => continue,
-                Err(e) => return Err(e)
+                kept += span.extent;
+                keep -= span.extent;
+            }else{
+                let n = self.truncate_index_block(span.base_sector, span.extent, level-1, keep, released)?;
+                kept += n;
+                keep -= n;
             }
         }
+
+        Ok(kept)
     }
 
-    fn cmp_nullstr_by_indirection(&mut self, name: &str, mut offset: u64, baseref: u128, indirection: u8, len: u64) -> crate::io::Result<Ordering>{
-        let mut name = name.as_bytes();
+    /// Moves the content of the stream described by `listing` to the layout with the fewest levels of indirection that holds it, after it has been truncated.
+    ///
+    /// Content which fits is moved inline (unless `allow_inline` is false), a tree whose root has a single data extent becomes that extent, and a root with a single single-sector index block is replaced by that block.
+    ///
+    /// Space which is no longer used is not released, but added to `released` as the base, number of sectors, and level of each data extent (level 1) or index block (level 2 and above).
+    /// It must be released with [`FilesystemAccess::free_released`] once `listing` is written back, so that the stream never refers to released space.
+    fn demote_stream_content(&mut self, listing: &mut StreamListing, allow_inline: bool, released: &mut Vec<(u128, u64, u8)>) -> crate::io::Result<()>{
         loop{
-            let mut buf = [0;1024];
-            match self.read_by_indirection(offset, &mut buf, baseref, indirection, len){
-                Ok(0) => return Err(crate::io::Error::UnexpectedEof),
-                Ok(n) => {
-                    let buf = &buf[..n];
+            let indirection = listing.flags.get_indirection();
 
-                    for (idx,b) in buf.iter().enumerate(){
-                        if *b==0{
-                            return Ok(idx.cmp(&name.len()))
-                        }
-                        else if name.len()==idx{
-                            return Ok(Ordering::Greater);
-                        }else{
-                            match buf[idx].cmp(&b){
-                                Ordering::Equal => continue,
-                                o => return Ok(o)
-                            }
+            if indirection==0{
+                return Ok(())
+            }
+
+            let demoted = if allow_inline && listing.size<=(listing.inline_data.len() as u64){
+                let mut inline_data: [u8;32] = Zeroable::zeroed();
+                self.read_fully_from_stream(&mut inline_data[..(listing.size as usize)], 0, listing)?;
+                match indirection{
+                    1 => if listing.size!=0{
+                        released.push((listing.content_ref, 1, 1));
+                    },
+                    indirection => released.push((listing.content_ref, 1, indirection as u8)),
+                }
+                listing.inline_data = inline_data;
+                listing.content_ref = 0;
+                0
+            }else if indirection==1{
+                return Ok(())
+            }else{
+                let root = listing.content_ref;
+
+                let demoted = match self.count_spans(root, 1)?{
+                    0 => {
+                        listing.content_ref = 0;
+                        1
+                    }
+                    1 => {
+                        let entry = self.read_span(root, 0)?;
+                        if indirection>2 && entry.extent!=1{
+                            return Ok(())
                         }
+                        listing.content_ref = entry.base_sector;
+                        indirection-1
                     }
-                    name = &name[n..];
-                    offset += n as u64;
-                }
-                Err(crate::io::Error::Interrupted) => continue,
-                Err(e) => return Err(e)
-            }
-        }
-    }
+                    _ => return Ok(())
+                };
 
-    fn cmp_nullstr_from_stream(&mut self, str: &str, pos: u64, stream: &StreamListing) -> crate::io::Result<Ordering>{
-        
+                released.push((root, 1, 1));
+                demoted
+            };
 
-        if pos>stream.size{
-            return Err(crate::io::Error::UnexpectedEof)
+            listing.flags = (listing.flags & !StreamFlags::INDIRECTION_MASK) | StreamFlags::indirection(demoted);
         }
+    }
 
-        let indirection = stream.flags.get_indirection() as u8;
-
+    /// Writes all of `buf` to the stream described by `listing` at `pos`, extending the stream if necessary, and writes the updated listing back to the Streams stream of `objid`.
+    ///
+    /// If `pos` is past the end of the stream, the space between the end and `pos` is filled with zeroes.
+    fn write_stream_inner(&mut self, objid: ObjectId, id: StreamId, listing: &mut StreamListing, pos: u64, buf: &[u8]) -> crate::io::Result<()>{
+        let end = pos.checked_add(buf.len() as u64).ok_or(crate::io::Error::InvalidInput)?;
+        let old_size = listing.size;
+        let new_size = old_size.max(end);
 
-        if indirection==0{
-            let str = str.as_bytes();
-            let base = &stream.inline_data[..(pos as usize)];
+        self.reserve_stream_content(listing, new_size)?;
 
-            let content = base.split(|n|*n==0).next().unwrap();
+        let indirection = listing.flags.get_indirection() as u8;
 
-            Ok(str.cmp(content))
+        if indirection==0{
+            if pos>old_size{
+                listing.inline_data[(old_size as usize)..(pos as usize)].fill(0);
+            }
+            listing.inline_data[(pos as usize)..(end as usize)].copy_from_slice(buf);
         }else{
-            self.cmp_nullstr_by_indirection(str, pos, stream.content_ref, indirection, stream.size)
+            let zeroes = [0u8;1024];
+            let mut gap = old_size;
+            while gap<pos{
+                let len = (pos-gap).min(1024);
+                self.write_fully_by_indirection(gap, &zeroes[..(len as usize)], listing.content_ref, indirection, new_size)?;
+                gap += len;
+            }
+            self.write_fully_by_indirection(pos, buf, listing.content_ref, indirection, new_size)?;
+        }
+
+        if indirection==0 || new_size!=old_size{
+            listing.size = new_size;
+            self.write_stream_listing(objid, id, listing)?;
         }
+
+        Ok(())
     }
 
-    pub fn read_from_stream(&mut self, buf: &mut [u8], pos: u64, stream: &StreamListing) -> crate::io::Result<usize>{
-        let max_len = buf.len().min(stream.size.saturating_sub(pos) as usize);
+    /// Writes all of `buf` to stream `id` of `objid` at `pos`, extending the stream if necessary.
+    ///
+    /// Inline streams, direct extents, and trees of [`VolumeSpan`]s are all supported. If `pos` is past the end of the stream, the space between the end and `pos` is filled with zeroes.
+    /// The size of the stream is updated in its listing in the Streams stream.
+    pub fn write_to_stream(&mut self, objid: ObjectId, id: StreamId, pos: u64, buf: &[u8]) -> crate::io::Result<()>{
+        let mut listing = self.get_stream_by_id(objid, id)?;
 
-        let buf = &mut buf[..max_len];
+        self.write_stream_inner(objid, id, &mut listing, pos, buf)
+    }
 
-        let indirection = stream.flags.get_indirection() as u8;
+    /// Shortens stream `id` of `objid` to `len` bytes, releasing the space which is no longer used.
+    ///
+    /// The stream is then moved to the layout with the fewest levels of indirection that holds it. The Streams stream is never moved inline, since [`Object`] has no space for inline data.
+    ///
+    /// Returns [`Error::InvalidInput`][crate::io::Error::InvalidInput] if `len` is greater than the size of the stream.
+    pub fn truncate_stream(&mut self, objid: ObjectId, id: StreamId, len: u64) -> crate::io::Result<()>{
+        let mut listing = self.get_stream_by_id(objid, id)?;
 
-        if indirection ==0{
-            buf.copy_from_slice(&stream.inline_data[(pos as usize)..][..max_len]);
+        if len>listing.size{
+            return Err(crate::io::Error::InvalidInput)
+        }
 
-            Ok(max_len)
-        }else{
-            self.read_by_indirection(pos, buf, stream.content_ref, indirection, stream.size)
+        let old_sectors = (listing.size>>10) + ((listing.size&1023)!=0) as u64;
+        let new_sectors = (len>>10) + ((len&1023)!=0) as u64;
+
+        let mut released = Vec::new();
+
+        match listing.flags.get_indirection(){
+            0 => listing.inline_data[(len as usize)..].fill(0),
+            1 => if new_sectors<old_sectors{
+                released.push((listing.content_ref+(new_sectors as u128), old_sectors-new_sectors, 1));
+            },
+            indirection => {
+                self.truncate_index_block(listing.content_ref, 1, indirection as u8, new_sectors, &mut released)?;
+            }
         }
-    }
 
-    pub fn read_fully_from_stream(&mut self, buf: &mut [u8], pos: u64, stream: &StreamListing) -> crate::io::Result<()>{
+        listing.size = len;
 
-        if (stream.size.saturating_sub(pos) as usize)<buf.len(){
-            return Err(crate::io::Error::UnexpectedEof)
+        self.demote_stream_content(&mut listing, id!=StreamId::STREAMS, &mut released)?;
+        self.write_stream_listing(objid, id, &listing)?;
+        self.free_released(&released)
+    }
+
+    /// Releases the data extents and index blocks collected by [`FilesystemAccess::demote_stream_content`] and [`FilesystemAccess::truncate_index_block`].
+    fn free_released(&mut self, released: &[(u128, u64, u8)]) -> crate::io::Result<()>{
+        for &(base, sectors, level) in released{
+            if level==1{
+                self.free_space(SectorPos(base), sectors)?;
+            }else{
+                self.free_index_block(base, sectors, level)?;
+            }
         }
 
-        let indirection = stream.flags.get_indirection() as u8;
-        if indirection==0{
-            buf.copy_from_slice(&stream.inline_data[(pos as usize)..][..buf.len()]);
+        Ok(())
+    }
 
-            Ok(())
+    /// Sets the length of stream `id` of `objid` to `len` bytes.
+    ///
+    /// If the stream grows, the new content is filled with zeroes. If it shrinks, the stream is truncated as by [`FilesystemAccess::truncate_stream`],
+    ///  which releases the data extents and index blocks that are no longer used.
+    pub fn set_stream_len(&mut self, objid: ObjectId, id: StreamId, len: u64) -> crate::io::Result<()>{
+        let mut listing = self.get_stream_by_id(objid, id)?;
+
+        if len<=listing.size{
+            self.truncate_stream(objid, id, len)
         }else{
-            self.read_fully_by_indirection(pos, buf, stream.content_ref, indirection, stream.size)
+            self.write_stream_inner(objid, id, &mut listing, len, &[])
         }
     }
 
-    pub fn get_stream_by_id(&mut self, objid: ObjectId, stream: StreamId) -> crate::io::Result<StreamListing>{
+    /// Releases the space used by the content of a stream. The listing itself is not modified.
+    fn free_stream_content(&mut self, listing: &StreamListing) -> crate::io::Result<()>{
+        match listing.flags.get_indirection(){
+            0 => Ok(()),
+            1 => {
+                let sectors = (listing.size>>10) + ((listing.size&1023)!=0) as u64;
+                if sectors==0{
+                    Ok(())
+                }else{
+                    self.free_space(SectorPos(listing.content_ref), sectors)
+                }
+            }
+            indirection => self.free_index_block(listing.content_ref, 1, indirection as u8)
+        }
+    }
+
+    /// Releases an index block, and every index block and data extent below it.
+    fn free_index_block(&mut self, block: u128, sectors: u64, level: u8) -> crate::io::Result<()>{
+        let count = self.count_spans(block, sectors)?;
+
+        for idx in 0..count{
+            let span = self.read_span(block, idx)?;
+
+            if level==2{
+                self.free_space(SectorPos(span.base_sector), span.extent)?;
+            }else{
+                self.free_index_block(span.base_sector, span.extent, level-1)?;
+            }
+        }
+
+        self.free_space(SectorPos(block), sectors)
+    }
+
+    /// Interns `str` in the Strings stream of `objid`, and returns its offset.
+    ///
+    /// If the stream already has an entry equal to `str`, its offset is returned. Otherwise, `str` is appended to the stream as a new entry.
+    ///
+    /// Returns [`Error::InvalidInput`][crate::io::Error::InvalidInput] if `str` contains a null byte.
+    pub fn intern_string(&mut self, objid: ObjectId, str: &str) -> crate::io::Result<NonZeroU64>{
+        if str.as_bytes().contains(&0){
+            return Err(crate::io::Error::InvalidInput)
+        }
+
         let obj = self.get_obj_by_id(objid)?;
+        let id = StreamId(obj.strings_stream.ok_or(crate::io::Error::NotFound)?.get());
+        let strings = self.get_stream_by_id(objid, id)?;
 
-        if obj.strong_ref==0{
-            // Note: weak_ref is tested by `get_obj_by_id`. Otherwise, condition should test weak_ref as well, since strong_ref has undefined content with weak_ref==0
-            return Err(crate::io::Error::NotFound)
+        let mut content = alloc::vec![0u8; strings.size as usize];
+        self.read_fully_from_stream(&mut content, 0, &strings)?;
+
+        let mut pos = 1;
+        while pos<content.len(){
+            let entry = content[pos..].split(|n|*n==0).next().unwrap();
+
+            if entry==str.as_bytes() && pos+entry.len()<content.len(){
+                // safety: pos starts at 1
+                return Ok(unsafe{NonZeroU64::new_unchecked(pos as u64)})
+            }
+
+            pos += entry.len()+1;
         }
 
-        let pos = stream.0*(size_of::<StreamListing>() as u64);
+        let mut entry = Vec::with_capacity(str.len()+2);
+        if content.is_empty(){
+            entry.push(0);
+        }
+        let pos = content.len().max(1) as u64;
+        entry.extend_from_slice(str.as_bytes());
+        entry.push(0);
 
-        if pos>=obj.streams_size{
-            return Err(crate::io::Error::NotFound)
+        self.write_to_stream(objid, id, strings.size, &entry)?;
+
+        // safety: pos is at least 1
+        Ok(unsafe{NonZeroU64::new_unchecked(pos)})
+    }
+
+    /// Sets the volume label.
+    ///
+    /// Labels longer than the inline label field are interned in the Strings stream of the root object and referred to by `label_ref`.
+    /// In that case, the inline label holds as much of the label as fits.
+    ///
+    /// Returns [`Error::InvalidInput`][crate::io::Error::InvalidInput] if `label` contains a null byte.
+    pub fn set_label(&mut self, label: &str) -> crate::io::Result<()>{
+        if label.contains('\0'){
+            return Err(crate::io::Error::InvalidInput)
         }
 
-        let mut listing = Zeroable::zeroed();
+        let desc = *self.get_or_read_descriptor()?;
 
-        self.read_fully_by_indirection(pos, bytemuck::bytes_of_mut(&mut listing), obj.streams_ref,obj.streams_indirection,obj.streams_size)?;
+        let label_ref = if label.len()>desc.label.len(){
+            let root = desc.root_object_id.ok_or(crate::io::Error::InvalidData)?;
+            Some(self.intern_string(root, label)?)
+        }else{
+            None
+        };
 
-        Ok(listing)
+        let mut prefix_len = label.len().min(desc.label.len());
+        while !label.is_char_boundary(prefix_len){
+            prefix_len -= 1;
+        }
+
+        let desc = self.get_or_read_descriptor()?;
+        desc.label_ref = label_ref;
+        desc.label = extend_str(&label[..prefix_len]);
+
+        self.label = Some(String::from(label));
+
+        Ok(())
     }
 
-    pub fn find_stream_by_id(&mut self, objid: ObjectId, stream: &str) -> crate::io::Result<(StreamId,StreamListing)>{
-        let mut idx = 0u64;
+    /// Creates a new object of type `ty`, and returns its id. The new object has a single strong reference, which is owned by the caller.
+    ///
+    /// The object is given a Streams, Strings, and SecurityDescriptor stream, and the content stream for `ty` (if any), which is `init_size` bytes of zeroes.
+    /// The Strings stream is initialized from `init_string_tab`, which is a sequence of null-terminated strings. A null byte is placed before the table, so that every string has a nonzero offset.
+    /// The SecurityDescriptor stream grants the ObjectOwner permission to `owner_uuid`.
+    ///
+    /// If the object table is full, it is grown via [`FilesystemAccess::grow_object_table`].
+    pub fn create_object(&mut self, init_size: u64, ty: ObjectType, init_string_tab: &str,owner_uuid: Uuid) -> crate::io::Result<ObjectId>{
+        let content_stream = match ty{
+            ObjectType::RegularFile => Some(consts::FILEDATA_STREAM),
+            ObjectType::Directory => Some(consts::DIRECTORYCONTENT_STREAM),
+            ObjectType::Symlink => Some(consts::SYMLINKTARGET_STREAM),
+            ObjectType::BlockDevice | ObjectType::CharDevice => Some(consts::DEVICEID_STREAM),
+            _ => None,
+        };
 
-        let obj = self.get_obj_by_id(objid)?;
-        
-        let strings_stream = obj.strings_stream.map(|id| self.get_stream_by_id(objid,StreamId(id.get()))).transpose()?;
+        if content_stream.is_none() && init_size!=0{
+            return Err(crate::io::Error::InvalidInput)
+        }
 
+        let desc = self.get_or_read_descriptor()?;
 
-        while (idx*(size_of::<StreamListing>() as u64))<obj.streams_size{
-            let mut listing: StreamListing = Zeroable::zeroed();
-            self.read_fully_by_indirection(idx*(size_of::<StreamListing>() as u64), bytemuck::bytes_of_mut(&mut listing), obj.streams_ref,obj.streams_indirection,obj.streams_size)?;
+        let objtab_end = desc.objtab_end;
+        let count = desc.objtab_size/(size_of::<Object>() as u64);
 
-            if let Some(nref) = listing.name_ref{
-                if let Some(strings) = &strings_stream{
-                    if self.cmp_nullstr_from_stream(stream, nref.get(), strings)?==Ordering::Equal{
-                        return Ok((StreamId(idx),listing));
-                    }
-                }
-            }else{
-                let name = listing.name.split(|f|*f==0).next().unwrap();
+        let mut slot = None;
 
-                if stream.as_bytes()==name{
-                    return Ok((StreamId(idx),listing));
+        for i in 1..=count{
+            self.stream.seek(SeekPos::StartSector(objtab_end.0))?;
+            self.stream.seek(SeekPos::Curr(-((i*(size_of::<Object>() as u64)) as i64)))?;
+            let mut obj: Object = Zeroable::zeroed();
+            self.stream.read_fully(bytemuck::bytes_of_mut(&mut obj))?;
+
+            if obj.weak_ref==0{
+                slot = Some(i);
+                break;
+            }
+        }
+
+        let id = match slot{
+            Some(i) => i,
+            None => {
+                self.grow_object_table()?;
+                count+1
+            }
+        };
+        // safety: i starts at `1`, and `count+1` cannot overflow since the object table would not fit on the volume
+        let id = ObjectId(unsafe{NonZeroU64::new_unchecked(id)});
+
+        let mut streams: [StreamListing;16] = Zeroable::zeroed();
+
+        [consts::STREAMS_STREAM, consts::STRINGS_STREAM, consts::SECURITYDESCRIPTOR_STREAM]
+            .into_iter()
+            .chain(content_stream)
+            .zip(&mut streams)
+            .for_each(|(stream, slot)| *slot = StreamListing{name: extend_str(stream), name_ref: None, flags: StreamFlags::REQUIRED, ..Zeroable::zeroed()});
+
+        let streams_size = core::mem::size_of_val(&streams) as u64;
+        let streams_base = self.allocate_contiguous_space(streams_size)?;
+
+        streams[0].content_ref = streams_base.0;
+        streams[0].size = streams_size;
+        streams[0].flags |= StreamFlags::indirection(1);
+
+        let mut strings = Vec::with_capacity(init_string_tab.len()+2);
+        strings.push(0);
+        strings.extend_from_slice(init_string_tab.as_bytes());
+        if !init_string_tab.is_empty() && !init_string_tab.ends_with('\0'){
+            strings.push(0);
+        }
+        self.write_initial_stream(&mut streams[1], &strings, strings.len() as u64)?;
+
+        let owner_row = SecurityDescriptorRow{
+            principal: owner_uuid.into(),
+            stream_id: StreamId::STREAMS,
+            flags_and_mode: SecurityDescRowMode::Permit.into(),
+            permission_name_ref: None,
+            permission_name: extend_str(consts::OBJECTOWNER_PERMISSION),
+        };
+        self.write_initial_stream(&mut streams[2], bytemuck::bytes_of(&owner_row), size_of::<SecurityDescriptorRow>() as u64)?;
+
+        if content_stream.is_some(){
+            self.write_initial_stream(&mut streams[3], &[], init_size)?;
+        }
+
+        self.stream.seek(SeekPos::StartSector(streams_base.0))?;
+        self.stream.write_all(bytemuck::cast_slice(&streams))?;
+
+        let obj = Object{
+            strong_ref: 1,
+            weak_ref: 1,
+            streams_size,
+            streams_ref: streams_base.0,
+            streams_indirection: 1,
+            ty,
+            flags: ObjectFlags::empty(),
+            strings_stream: NonZeroU64::new(1),
+            ..Zeroable::zeroed()
+        };
+
+        self.write_obj(id, &obj)?;
+
+        Ok(id)
+    }
+
+    /// Releases a strong reference to `objid`.
+    ///
+    /// When the last strong reference is released, every stream of the object is released, including the Streams stream, and the weak reference collectively held by the strong references is released.
+    /// If the object is a directory, the reference held by each of its entries is then released as well.
+    /// The object table entry remains until the last weak reference is released.
+    ///
+    /// The object is detached from its streams before their space is released.
+    ///
+    /// Returns [`Error::InvalidInput`][crate::io::Error::InvalidInput] if the object has no strong references.
+    pub fn release_strong(&mut self, objid: ObjectId) -> crate::io::Result<()>{
+        let mut obj = self.get_obj_by_id(objid)?;
+
+        if obj.strong_ref==0{
+            return Err(crate::io::Error::InvalidInput)
+        }
+
+        obj.strong_ref -= 1;
+
+        if obj.strong_ref!=0{
+            return self.write_obj(objid, &obj)
+        }
+
+        let streams = StreamListing{
+            flags: StreamFlags::indirection(obj.streams_indirection as u64),
+            content_ref: obj.streams_ref,
+            size: obj.streams_size,
+            ..Zeroable::zeroed()
+        };
+
+        let mut listings = Vec::new();
+        let mut pos = size_of::<StreamListing>() as u64;
+        while pos<obj.streams_size{
+            let mut listing: StreamListing = Zeroable::zeroed();
+            self.read_fully_by_indirection(pos, bytemuck::bytes_of_mut(&mut listing), obj.streams_ref, obj.streams_indirection, obj.streams_size)?;
+            listings.push(listing);
+            pos += size_of::<StreamListing>() as u64;
+        }
+
+        let mut children = Vec::new();
+        if obj.ty==ObjectType::Directory{
+            let (_, stream) = self.find_stream_by_id(objid, consts::DIRECTORYCONTENT_STREAM)?;
+            let len = stream.size/(size_of::<DirectoryElement>() as u64);
+            for i in 0..len{
+                let mut element: DirectoryElement = Zeroable::zeroed();
+                self.read_fully_from_stream(bytemuck::bytes_of_mut(&mut element), i*(size_of::<DirectoryElement>() as u64), &stream)?;
+                if let Some(child) = element.objidx{
+                    children.push((child, element.flags.contains(DirectoryElementFlags::WEAK)));
                 }
             }
-            idx+=1;
         }
-        
-        Err(crate::io::Error::NotFound)
+
+        let detached = Object{
+            streams_ref: 0,
+            streams_size: 0,
+            streams_indirection: 0,
+            strings_stream: None,
+            ..obj
+        };
+
+        self.write_obj(objid, &detached)?;
+        self.release_weak(objid)?;
+
+        for listing in &listings{
+            self.free_stream_content(listing)?;
+        }
+
+        self.free_stream_content(&streams)?;
+
+        for (child, weak) in children{
+            self.release_ref(child, weak)?;
+        }
+
+        Ok(())
     }
 
+    /// Releases a weak reference to `objid`. When the last weak reference is released, the object table entry is cleared so that it can be reused by [`FilesystemAccess::create_object`].
+    ///
+    /// Returns [`Error::InvalidInput`][crate::io::Error::InvalidInput] if the only weak reference remaining is the one held by the strong references.
+    pub fn release_weak(&mut self, objid: ObjectId) -> crate::io::Result<()>{
+        let mut obj = self.get_obj_by_id(objid)?;
 
-    pub fn search_directory(&mut self, objid: ObjectId, subfilename: &str) -> crate::io::Result<ObjectId>{
-        let obj = self.get_obj_by_id(objid)?;
+        if obj.strong_ref!=0 && obj.weak_ref==1{
+            return Err(crate::io::Error::InvalidInput)
+        }
 
-        let (_,stream) = self.find_stream_by_id(objid, consts::DIRECTORYCONTENT_STREAM)?;
+        obj.weak_ref -= 1;
 
-        let strings_stream = obj.strings_stream.map(|id| self.get_stream_by_id(objid,StreamId(id.get()))).transpose()?;
+        if obj.weak_ref==0{
+            obj = Zeroable::zeroed();
+        }
+
+        self.write_obj(objid, &obj)
+    }
+
+    /// Adds an entry named `name` for `target` to the directory `dir`, without changing the reference counts of `target`.
+    ///
+    /// Names longer than [`DirectoryElement::name`] are interned in the Strings stream of `dir` and referred to by `name_index`.
+    /// The entry is placed in the first empty slot of the DirectoryContent stream, or appended to it if there is none.
+    fn insert_dir_entry(&mut self, dir: ObjectId, name: &str, target: ObjectId, flags: DirectoryElementFlags) -> crate::io::Result<()>{
+        if !is_valid_entry_name(name) || target==dir{
+            return Err(crate::io::Error::InvalidInput)
+        }
+
+        if self.get_obj_by_id(dir)?.ty!=ObjectType::Directory{
+            return Err(crate::io::Error::InvalidInput)
+        }
+
+        match self.search_directory(dir, name){
+            Ok(_) => return Err(crate::io::Error::AlreadyExists),
+            Err(crate::io::Error::NotFound) => {}
+            Err(e) => return Err(e)
+        }
+
+        let (id, stream) = self.find_stream_by_id(dir, consts::DIRECTORYCONTENT_STREAM)?;
+
+        let mut element = DirectoryElement{
+            objidx: Some(target),
+            name_index: None,
+            flags,
+            name: Zeroable::zeroed(),
+        };
+
+        if name.len()>element.name.len(){
+            element.name_index = Some(self.intern_string(dir, name)?);
+        }else{
+            element.name = extend_str(name);
+        }
 
         let len = stream.size/(size_of::<DirectoryElement>() as u64);
+        let mut slot = len;
         for i in 0..len{
+            let mut existing: DirectoryElement = Zeroable::zeroed();
+
+            self.read_fully_from_stream(bytemuck::bytes_of_mut(&mut existing), i*(size_of::<DirectoryElement>() as u64), &stream)?;
+
+            if existing.objidx.is_none(){
+                slot = i;
+                break;
+            }
+        }
+
+        self.write_to_stream(dir, id, slot*(size_of::<DirectoryElement>() as u64), bytemuck::bytes_of(&element))
+    }
+
+    /// Acquires a strong reference to `target`, or a weak reference if `weak` is set.
+    fn acquire_ref(&mut self, target: ObjectId, weak: bool) -> crate::io::Result<()>{
+        let mut obj = self.get_obj_by_id(target)?;
+
+        if weak{
+            obj.weak_ref = obj.weak_ref.checked_add(1).ok_or(crate::io::Error::InvalidInput)?;
+        }else if obj.strong_ref==0{
+            return Err(crate::io::Error::NotFound)
+        }else{
+            obj.strong_ref = obj.strong_ref.checked_add(1).ok_or(crate::io::Error::InvalidInput)?;
+        }
+
+        self.write_obj(target, &obj)
+    }
+
+    fn release_ref(&mut self, target: ObjectId, weak: bool) -> crate::io::Result<()>{
+        if weak{
+            self.release_weak(target)
+        }else{
+            self.release_strong(target)
+        }
+    }
+
+    /// Adds an entry named `name` for `target` to the directory `dir`.
+    ///
+    /// The entry holds a strong reference to `target`, or a weak reference if `flags` contains [`DirectoryElementFlags::WEAK`], which is acquired before the entry is written.
+    ///
+    /// Objects do not refer to their parent directories, so only a directory linked into itself is detected as a cycle.
+    /// A directory given a strong entry in one of its own descendants is never released, nor are the objects it refers to.
+    ///
+    /// Returns [`Error::AlreadyExists`][crate::io::Error::AlreadyExists] if `dir` already has an entry named `name`,
+    ///  and [`Error::InvalidInput`][crate::io::Error::InvalidInput] if `dir` is not a directory, `target` is `dir`, or `name` is empty, `.` or `..`, or contains a `/` or null byte.
+    pub fn link(&mut self, dir: ObjectId, name: &str, target: ObjectId, flags: DirectoryElementFlags) -> crate::io::Result<()>{
+        let weak = flags.contains(DirectoryElementFlags::WEAK);
+
+        self.acquire_ref(target, weak)?;
+
+        if let Err(e) = self.insert_dir_entry(dir, name, target, flags){
+            self.release_ref(target, weak)?;
+            return Err(e)
+        }
+
+        Ok(())
+    }
+
+    /// Clears the entry in `slot` of the DirectoryContent stream of `dir`, and removes any empty slots from the end of the stream.
+    fn remove_dir_slot(&mut self, dir: ObjectId, slot: u64) -> crate::io::Result<()>{
+        let (id, stream) = self.find_stream_by_id(dir, consts::DIRECTORYCONTENT_STREAM)?;
+
+        let elem_size = size_of::<DirectoryElement>() as u64;
+
+        self.write_to_stream(dir, id, slot*elem_size, bytemuck::bytes_of(&DirectoryElement::zeroed()))?;
+
+        let mut len = stream.size/elem_size;
+        while len>0{
             let mut element: DirectoryElement = Zeroable::zeroed();
+            self.read_fully_from_stream(bytemuck::bytes_of_mut(&mut element), (len-1)*elem_size, &stream)?;
+            if element.objidx.is_some(){
+                break;
+            }
+            len -= 1;
+        }
 
-            self.read_from_stream(bytemuck::bytes_of_mut(&mut element), i*(size_of::<DirectoryElement>() as u64), &stream)?;
+        if len*elem_size!=stream.size{
+            self.set_stream_len(dir, id, len*elem_size)?;
+        }
 
-            if let Some(nameref) = element.name_index{
-                if let Some(strings) = &strings_stream{
-                    if self.cmp_nullstr_from_stream(subfilename, nameref.get(), strings)?.is_eq(){
-                        return element.objidx.ok_or(crate::io::Error::NotFound);
-                    }
-                }
-            }else{
-                let name = element.name.split(|f|*f==0).next().unwrap();
+        Ok(())
+    }
 
-                if subfilename.as_bytes()==name{
-                    return element.objidx.ok_or(crate::io::Error::NotFound);
-                }
+    /// Removes the entry named `name` from the directory `dir`, and releases the reference it held to its target.
+    pub fn unlink(&mut self, dir: ObjectId, name: &str) -> crate::io::Result<()>{
+        let (slot, element) = self.find_dir_entry(dir, name)?;
+
+        self.remove_dir_slot(dir, slot)?;
+
+        match element.objidx{
+            Some(target) => self.release_ref(target, element.flags.contains(DirectoryElementFlags::WEAK)),
+            None => Ok(())
+        }
+    }
+
+    /// Renames the entry `src_name` in `src_dir` to `dst_name` in `dst_dir`, replacing any existing entry named `dst_name`.
+    ///
+    /// Within one directory, the change is made by a single write of one entry: a rename without a replacement rewrites the entry in place, and a replacement overwrites the existing entry before the source entry is cleared.
+    /// In every case, an extra reference to the target is held until the source entry is cleared, so a crash at any point can only leave reference counts too high, never too low.
+    ///
+    /// Moving a directory into one of its own subdirectories is not detected, since objects do not refer to their parent directories. The directory is then unreachable, and is never released.
+    ///
+    /// Returns [`Error::InvalidInput`][crate::io::Error::InvalidInput] if `dst_dir` is not a directory or is the target of the entry, or `dst_name` is not a valid entry name.
+    pub fn rename(&mut self, src_dir: ObjectId, src_name: &str, dst_dir: ObjectId, dst_name: &str) -> crate::io::Result<()>{
+        if !is_valid_entry_name(dst_name){
+            return Err(crate::io::Error::InvalidInput)
+        }
+
+        if self.get_obj_by_id(dst_dir)?.ty!=ObjectType::Directory{
+            return Err(crate::io::Error::InvalidInput)
+        }
+
+        let (src_slot, element) = self.find_dir_entry(src_dir, src_name)?;
+        let target = element.objidx.ok_or(crate::io::Error::NotFound)?;
+        let weak = element.flags.contains(DirectoryElementFlags::WEAK);
+
+        if target==dst_dir{
+            return Err(crate::io::Error::InvalidInput)
+        }
+
+        if src_dir==dst_dir && src_name==dst_name{
+            return Ok(())
+        }
+
+        let existing = match self.find_dir_entry(dst_dir, dst_name){
+            Ok(existing) => Some(existing),
+            Err(crate::io::Error::NotFound) => None,
+            Err(e) => return Err(e)
+        };
+
+        if existing.is_none() && src_dir!=dst_dir{
+            self.acquire_ref(target, weak)?;
+            if let Err(e) = self.insert_dir_entry(dst_dir, dst_name, target, element.flags){
+                self.release_ref(target, weak)?;
+                return Err(e)
             }
+            self.remove_dir_slot(src_dir, src_slot)?;
+            return self.release_ref(target, weak)
         }
 
+        let mut renamed = DirectoryElement{
+            name_index: None,
+            name: Zeroable::zeroed(),
+            ..element
+        };
 
-        Err(crate::io::Error::NotFound)
+        if dst_name.len()>renamed.name.len(){
+            renamed.name_index = Some(self.intern_string(dst_dir, dst_name)?);
+        }else{
+            renamed.name = extend_str(dst_name);
+        }
+
+        let (dst_id, _) = self.find_stream_by_id(dst_dir, consts::DIRECTORYCONTENT_STREAM)?;
+        let elem_size = size_of::<DirectoryElement>() as u64;
+
+        let dst_slot = match existing{
+            Some((dst_slot, _)) => dst_slot,
+            None => return self.write_to_stream(dst_dir, dst_id, src_slot*elem_size, bytemuck::bytes_of(&renamed))
+        };
+
+        self.acquire_ref(target, weak)?;
+        if let Err(e) = self.write_to_stream(dst_dir, dst_id, dst_slot*elem_size, bytemuck::bytes_of(&renamed)){
+            self.release_ref(target, weak)?;
+            return Err(e)
+        }
+
+        if let Some((_, DirectoryElement{objidx: Some(old), flags, ..})) = existing{
+            self.release_ref(old, flags.contains(DirectoryElementFlags::WEAK))?;
+        }
+
+        self.remove_dir_slot(src_dir, src_slot)?;
+        self.release_ref(target, weak)
+    }
+
+    /// Creates a new object of type `ty` as by [`FilesystemAccess::create_object`], and inserts it into `dir` as `name`.
+    ///
+    /// The reference returned by `create_object` is given to the new directory entry. If the entry cannot be inserted, the object is released.
+    fn create_in_directory(&mut self, dir: ObjectId, name: &str, init_size: u64, ty: ObjectType, owner_uuid: Uuid) -> crate::io::Result<ObjectId>{
+        if !is_valid_entry_name(name){
+            return Err(crate::io::Error::InvalidInput)
+        }
+
+        let objid = self.create_object(init_size, ty, "", owner_uuid)?;
+
+        if let Err(e) = self.insert_dir_entry(dir, name, objid, DirectoryElementFlags::empty()){
+            self.release_strong(objid)?;
+            return Err(e)
+        }
+
+        Ok(objid)
+    }
+
+    /// Creates a symbolic link named `name` in `dir`, which refers to `target`, and returns its id.
+    ///
+    /// `target` is stored as-is in the SymlinkTarget stream, and is not required to exist.
+    pub fn create_symlink(&mut self, dir: ObjectId, name: &str, target: &str, owner_uuid: Uuid) -> crate::io::Result<ObjectId>{
+        if target.is_empty() || target.as_bytes().contains(&0){
+            return Err(crate::io::Error::InvalidInput)
+        }
+
+        let objid = self.create_in_directory(dir, name, 0, ObjectType::Symlink, owner_uuid)?;
+
+        let (id, _) = self.find_stream_by_id(objid, consts::SYMLINKTARGET_STREAM)?;
+        self.write_to_stream(objid, id, 0, target.as_bytes())?;
+
+        Ok(objid)
+    }
+
+    /// Adds an empty stream named `name` to `objid`, and returns its id.
+    ///
+    /// The first unused listing in the Streams stream is reused, and the Streams stream is extended if there is none.
+    /// Names longer than [`StreamListing::name`] are interned in the Strings stream of `objid` and referred to by `name_ref`.
+    ///
+    /// Returns [`Error::AlreadyExists`][crate::io::Error::AlreadyExists] if `objid` already has a stream named `name`.
+    pub fn create_stream(&mut self, objid: ObjectId, name: &str, flags: StreamFlags) -> crate::io::Result<StreamId>{
+        if name.is_empty() || name.as_bytes().contains(&0){
+            return Err(crate::io::Error::InvalidInput)
+        }
+
+        match self.find_stream_by_id(objid, name){
+            Ok(_) => return Err(crate::io::Error::AlreadyExists),
+            Err(crate::io::Error::NotFound) => {}
+            Err(e) => return Err(e)
+        }
+
+        let obj = self.get_obj_by_id(objid)?;
+        let count = obj.streams_size/(size_of::<StreamListing>() as u64);
+
+        let mut id = StreamId(count);
+        for i in 1..count{
+            let listing = self.get_stream_by_id(objid, StreamId(i))?;
+            if listing.name[0]==0 && listing.name_ref.is_none(){
+                id = StreamId(i);
+                break;
+            }
+        }
+
+        let mut listing = StreamListing{
+            flags: flags & !StreamFlags::INDIRECTION_MASK,
+            ..Zeroable::zeroed()
+        };
+
+        if name.len()>listing.name.len(){
+            listing.name_ref = Some(self.intern_string(objid, name)?);
+        }else{
+            listing.name = extend_str(name);
+        }
+
+        if id.0==count{
+            self.write_to_stream(objid, StreamId::STREAMS, id.0*(size_of::<StreamListing>() as u64), bytemuck::bytes_of(&listing))?;
+        }else{
+            self.write_stream_listing(objid, id, &listing)?;
+        }
+
+        Ok(id)
+    }
+
+    /// Creates a block or character device named `name` in `dir`, and returns its id.
+    ///
+    /// `devid` is stored in the DeviceId stream. If `legacy` is given, it is stored in a LegacyDeviceNumber stream for the benefit of systems which identify devices by major and minor number.
+    pub fn create_device_node(&mut self, dir: ObjectId, name: &str, ty: ObjectType, devid: DeviceId, legacy: Option<LegacyDeviceNumber>, owner_uuid: Uuid) -> crate::io::Result<ObjectId>{
+        if ty!=ObjectType::BlockDevice && ty!=ObjectType::CharDevice{
+            return Err(crate::io::Error::InvalidInput)
+        }
+
+        let objid = self.create_in_directory(dir, name, 0, ty, owner_uuid)?;
+
+        let (id, _) = self.find_stream_by_id(objid, consts::DEVICEID_STREAM)?;
+        self.write_to_stream(objid, id, 0, bytemuck::bytes_of(&devid))?;
+
+        if let Some(legacy) = legacy{
+            let id = self.create_stream(objid, consts::LEGACYDEVICENUMBER_STREAM, StreamFlags::empty())?;
+            self.write_to_stream(objid, id, 0, bytemuck::bytes_of(&legacy))?;
+        }
+
+        Ok(objid)
+    }
+
+    /// Creates a FIFO or Unix socket named `name` in `dir`, and returns its id.
+    ///
+    /// These objects have no content of their own, so only the Streams, Strings, and SecurityDescriptor streams are created.
+    /// Returns [`Error::InvalidInput`][crate::io::Error::InvalidInput] if `ty` is not [`ObjectType::PosixFifo`] or [`ObjectType::UnixSocket`].
+    pub fn create_special(&mut self, dir: ObjectId, name: &str, ty: ObjectType, owner_uuid: Uuid) -> crate::io::Result<ObjectId>{
+        if ty!=ObjectType::PosixFifo && ty!=ObjectType::UnixSocket{
+            return Err(crate::io::Error::InvalidInput)
+        }
+
+        self.create_in_directory(dir, name, 0, ty, owner_uuid)
     }
 
-}
\ No newline at end of file
+    /// Appends a row to the SecurityDescriptor stream of `objid`, which sets the mode and flags in `flags_and_mode` for `principal` and `permission`, on the whole object if `stream_id` is [`StreamId::STREAMS`], and on stream `stream_id` otherwise.
+    ///
+    /// Permission names longer than [`SecurityDescriptorRow::permission_name`] are interned in the Strings stream of `objid` and referred to by `permission_name_ref`.
+    ///
+    /// Returns [`Error::InvalidInput`][crate::io::Error::InvalidInput] if the row has an unknown mode and the `REQUIRED` flag, and [`Error::Unsupported`][crate::io::Error::Unsupported] if the stream has such a row already.
+    pub fn add_security_row(&mut self, objid: ObjectId, principal: u128, stream_id: StreamId, flags_and_mode: SecurityDescRowFlags, permission: &str) -> crate::io::Result<()>{
+        if permission.is_empty() || permission.as_bytes().contains(&0){
+            return Err(crate::io::Error::InvalidInput)
+        }
+
+        let mut row = SecurityDescriptorRow{
+            principal,
+            stream_id,
+            flags_and_mode,
+            permission_name_ref: None,
+            permission_name: Zeroable::zeroed(),
+        };
+
+        if !row_is_understood(&row){
+            return Err(crate::io::Error::InvalidInput)
+        }
+
+        if stream_id!=StreamId::STREAMS{
+            let listing = self.get_stream_by_id(objid, stream_id)?;
+            if listing.name[0]==0 && listing.name_ref.is_none(){
+                return Err(crate::io::Error::NotFound)
+            }
+        }
+
+        if !self.read_security_rows(objid)?.iter().all(row_is_understood){
+            return Err(crate::io::Error::Unsupported)
+        }
+
+        if permission.len()>row.permission_name.len(){
+            row.permission_name_ref = Some(self.intern_string(objid, permission)?);
+        }else{
+            row.permission_name = extend_str(permission);
+        }
+
+        let (id, stream) = self.find_stream_by_id(objid, consts::SECURITYDESCRIPTOR_STREAM)?;
+
+        self.write_to_stream(objid, id, stream.size, bytemuck::bytes_of(&row))
+    }
+
+    /// Removes every row of the SecurityDescriptor stream of `objid` for `principal` and `permission`, regardless of mode or stream, and returns the number of rows removed.
+    ///
+    /// `principal` and `permission` are matched exactly, so [`consts::DEFAULT_PRINCIPAL`] and [`consts::ALL_PERMISSIONS`] only remove rows which name them.
+    ///
+    /// Returns [`Error::Unsupported`][crate::io::Error::Unsupported] if the stream has a row with an unknown mode and the `REQUIRED` flag.
+    pub fn remove_security_rows(&mut self, objid: ObjectId, principal: u128, permission: &str) -> crate::io::Result<usize>{
+        let obj = self.get_obj_by_id(objid)?;
+        let strings = obj.strings_stream.map(|id| self.get_stream_by_id(objid,StreamId(id.get()))).transpose()?;
+
+        let rows = self.read_security_rows(objid)?;
+
+        if !rows.iter().all(row_is_understood){
+            return Err(crate::io::Error::Unsupported)
+        }
+
+        let mut kept = Vec::with_capacity(rows.len());
+        for row in &rows{
+            if row.principal!=principal || !self.row_names_permission(row, permission, strings.as_ref())?{
+                kept.push(*row);
+            }
+        }
+
+        let removed = rows.len()-kept.len();
+
+        if removed!=0{
+            self.write_security_rows(objid, &kept)?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Replaces the content of the SecurityDescriptor stream of `objid` with `rows`.
+    fn write_security_rows(&mut self, objid: ObjectId, rows: &[SecurityDescriptorRow]) -> crate::io::Result<()>{
+        let (id, _) = self.find_stream_by_id(objid, consts::SECURITYDESCRIPTOR_STREAM)?;
+        self.write_to_stream(objid, id, 0, bytemuck::cast_slice(rows))?;
+        self.set_stream_len(objid, id, core::mem::size_of_val(rows) as u64)
+    }
+
+    /// Sets the POSIX owner, group, and mode of `objid`, stored in its LegacySecurityDescriptor stream, which is created if necessary.
+    ///
+    /// `owner` and `group` are the principals which correspond to `attrs.sd_uid` and `attrs.sd_gid`. The rows synthesized by a previous call, for any principal, and the Read, Write, and Execute Permit and Deny rows
+    ///  for the whole object for `owner`, `group`, and [`consts::DEFAULT_PRINCIPAL`] are replaced by the rows synthesized by [`LegacySecurityDescriptor::synthesize_rows`], so that the SecurityDescriptor stream agrees with the mode.
+    /// Other rows are kept, so a Forbid row still takes precedence over the mode.
+    ///
+    /// Returns [`Error::Unsupported`][crate::io::Error::Unsupported] if the SecurityDescriptor stream has a row with an unknown mode and the `REQUIRED` flag.
+    pub fn set_posix_attrs(&mut self, objid: ObjectId, attrs: LegacySecurityDescriptor, owner: u128, group: u128) -> crate::io::Result<()>{
+        let rows = self.read_security_rows(objid)?;
+
+        if !rows.iter().all(row_is_understood){
+            return Err(crate::io::Error::Unsupported)
+        }
+
+        let mut kept = Vec::with_capacity(rows.len()+9);
+        for row in rows{
+            let replaced = row.flags_and_mode.contains(LegacySecurityDescriptor::SYNTHESIZED_ROW)
+                || ((row.principal==owner || row.principal==group || row.principal==consts::DEFAULT_PRINCIPAL)
+                    && row.stream_id==StreamId::STREAMS
+                    && row.permission_name_ref.is_none()
+                    && matches!(row.flags_and_mode.mode(), SecurityDescRowMode::Permit | SecurityDescRowMode::Deny)
+                    && [consts::READ_PERMISSION, consts::WRITE_PERMISSION, consts::EXECUTE_PERMISION].iter().any(|perm| row.permission_name==extend_str::<24>(perm)));
+
+            if !replaced{
+                kept.push(row);
+            }
+        }
+
+        kept.extend(attrs.synthesize_rows(owner, group));
+        self.write_security_rows(objid, &kept)?;
+
+        let id = match self.find_stream_by_id(objid, consts::LEGACYSECURITYDESCRIPTOR_STREAM){
+            Ok((id, _)) => id,
+            Err(crate::io::Error::NotFound) => self.create_stream(objid, consts::LEGACYSECURITYDESCRIPTOR_STREAM, StreamFlags::empty())?,
+            Err(e) => return Err(e)
+        };
+
+        self.write_to_stream(objid, id, 0, bytemuck::bytes_of(&attrs))
+    }
+
+    /// Doubles the size of the object table.
+    ///
+    /// The table grows downwards from `objtab_end`, so the new space is reserved immediately below the current table if it is free.
+    /// Otherwise, the table is relocated to a new reservation, placing the existing entries at the top so that every existing [`ObjectId`] is unchanged.
+    pub fn grow_object_table(&mut self) -> crate::io::Result<()>{
+        let desc = self.get_or_read_descriptor()?;
+        let objtab_end = desc.objtab_end.0;
+        let old_size = desc.objtab_size;
+        let old_sectors = (old_size>>10) + ((old_size&1023)!=0) as u64;
+
+        let new_sectors = (old_sectors*2).max(1);
+        let new_size = new_sectors<<10;
+        let old_base = objtab_end-(old_sectors as u128);
+
+        let new_end = if old_base>=((new_sectors-old_sectors) as u128) && self.try_reserve_at(SectorPos(old_base-((new_sectors-old_sectors) as u128)), new_sectors-old_sectors)?{
+            objtab_end
+        }else{
+            let new_base = self.allocate_contiguous_space(new_size)?.0;
+            let new_end = new_base+(new_sectors as u128);
+
+            let mut buf = [0u8;1024];
+            for i in 1..=old_sectors{
+                self.stream.seek(SeekPos::StartSector(objtab_end-(i as u128)))?;
+                self.stream.read_fully(&mut buf)?;
+                self.stream.seek(SeekPos::StartSector(new_end-(i as u128)))?;
+                self.stream.write_all(&buf)?;
+            }
+            if old_sectors!=0{
+                self.free_space(SectorPos(old_base), old_sectors)?;
+            }
+            new_end
+        };
+
+        self.stream.seek(SeekPos::StartSector(new_end-(new_sectors as u128)))?;
+        self.stream.write_zeroes(((new_sectors-old_sectors)<<10) as usize)?;
+
+        let desc = self.get_or_read_descriptor()?;
+        desc.objtab_end = SectorPos(new_end);
+        desc.objtab_size = new_size;
+
+        Ok(())
+    }
+
+    fn read_alloc_table(&mut self) -> crate::io::Result<Vec<VolumeSpan>>{
+        let desc = self.get_or_read_descriptor()?;
+        let begin = desc.alloc_tab_begin;
+        let count = desc.alloc_tab_size/(size_of::<VolumeSpan>() as u64);
+
+        let mut table = alloc::vec![VolumeSpan::zeroed(); count as usize];
+
+        self.stream.seek(SeekPos::Start(begin.0))?;
+        self.stream.read_fully(bytemuck::cast_slice_mut(&mut table))?;
+
+        Ok(table)
+    }
+
+    fn write_alloc_entry(&mut self, idx: usize, span: &VolumeSpan) -> crate::io::Result<()>{
+        let begin = self.get_or_read_descriptor()?.alloc_tab_begin;
+
+        self.stream.seek(SeekPos::Start(begin.0+((idx*size_of::<VolumeSpan>()) as u64)))?;
+        self.stream.write_all(bytemuck::bytes_of(span))
+    }
+
+    /// Returns the range of sectors occupied by the allocation table.
+    fn alloc_table_sectors(&mut self) -> crate::io::Result<(u128, u128)>{
+        let desc = self.get_or_read_descriptor()?;
+        let end = desc.alloc_tab_begin.0+desc.alloc_tab_size;
+
+        Ok(((desc.alloc_tab_begin.0>>10) as u128, ((end>>10) + ((end&1023)!=0) as u64) as u128))
+    }
+
+    /// Records a reservation of `extent` sectors starting at `base` in `table`, which must not overlap an existing span.
+    ///
+    /// A span which covers exactly the sectors of the allocation table is never merged with, so that [`FilesystemAccess::grow_alloc_table`] can release it.
+    fn record_reservation(&mut self, table: &mut [VolumeSpan], base: u128, extent: u64) -> crate::io::Result<()>{
+        let end = base+(extent as u128);
+        let (tab_begin, tab_end) = self.alloc_table_sectors()?;
+        let mergeable = |span: &VolumeSpan| span.extent!=0 && (span.base_sector, span.base_sector+(span.extent as u128))!=(tab_begin, tab_end);
+
+        let prev = table.iter().position(|span| mergeable(span) && span.base_sector+(span.extent as u128)==base);
+        let next = table.iter().position(|span| mergeable(span) && span.base_sector==end);
+
+        match (prev, next){
+            (Some(p), Some(n)) => {
+                table[p].extent += extent + table[n].extent;
+                table[n] = Zeroable::zeroed();
+                self.write_alloc_entry(p, &table[p])?;
+                self.write_alloc_entry(n, &table[n])?;
+            }
+            (Some(p), None) => {
+                table[p].extent += extent;
+                self.write_alloc_entry(p, &table[p])?;
+            }
+            (None, Some(n)) => {
+                table[n].base_sector = base;
+                table[n].extent += extent;
+                self.write_alloc_entry(n, &table[n])?;
+            }
+            (None, None) => {
+                let slot = table.iter().position(|span| span.extent==0).ok_or(crate::io::Error::NoSpace)?;
+                table[slot] = VolumeSpan{base_sector: base, extent, __reserved: 0};
+                self.write_alloc_entry(slot, &table[slot])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reserves `size` bytes (rounded up to whole sectors) of contiguous space on the volume, and returns the first sector of the reservation.
+    ///
+    /// The allocation table is a list of reserved [`VolumeSpan`]s (with unused slots having an `extent` of 0).
+    /// The reservation is merged into an adjacent span where possible, and otherwise placed in an unused slot. If the table is full, it is grown first.
+    ///
+    /// Returns [`Error::NoSpace`][crate::io::Error::NoSpace] if there is no free run of sufficient size, or the allocation table is full and cannot be grown.
+    pub fn allocate_contiguous_space(&mut self, size: u64) -> crate::io::Result<SectorPos>{
+        let extent = (size>>10) + ((size&1023)!=0) as u64;
+
+        if extent==0{
+            return Err(crate::io::Error::InvalidInput)
+        }
+
+        self.ensure_alloc_slot()?;
+
+        let mut table = self.read_alloc_table()?;
+        let base = self.find_free_run(&table, extent)?;
+
+        self.record_reservation(&mut table, base, extent)?;
+
+        Ok(SectorPos(base))
+    }
+
+    /// Finds the first run of `extent` free sectors according to `table`, without reserving it.
+    fn find_free_run(&mut self, table: &[VolumeSpan], extent: u64) -> crate::io::Result<u128>{
+        let volsize = self.stream.stream_length()?.sector;
+
+        let mut order: Vec<usize> = (0..table.len()).filter(|&i| table[i].extent!=0).collect();
+        order.sort_by_key(|&i| table[i].base_sector);
+
+        let mut base = 0u128;
+
+        for &i in &order{
+            let span = table[i];
+            if span.base_sector>=base && span.base_sector-base >= extent as u128{
+                return Ok(base)
+            }
+            base = base.max(span.base_sector+(span.extent as u128));
+        }
+
+        if volsize<base || volsize-base < extent as u128{
+            return Err(crate::io::Error::NoSpace)
+        }
+
+        Ok(base)
+    }
+
+    /// Ensures that the allocation table has at least one unused slot, growing it via [`FilesystemAccess::grow_alloc_table`] if it is full.
+    ///
+    /// Reserving or releasing space needs at most one unused slot, so this is done before the table is searched, which ensures that growing the table cannot claim the space being reserved.
+    fn ensure_alloc_slot(&mut self) -> crate::io::Result<()>{
+        if self.read_alloc_table()?.iter().all(|span| span.extent!=0){
+            self.grow_alloc_table()?;
+        }
+
+        Ok(())
+    }
+
+    /// Doubles the size of the allocation table.
+    ///
+    /// The table is extended in place if the sectors immediately after it are free. Otherwise, it is copied to a new reservation, and `alloc_tab_begin` is updated.
+    /// The new reservation is recorded as a span of its own, which is released when the table is moved again.
+    /// The table placed by [`FilesystemAccess::format`] has no span of its own, since it may share sectors with the reserved boot sectors, and so its location remains reserved.
+    pub fn grow_alloc_table(&mut self) -> crate::io::Result<()>{
+        let desc = self.get_or_read_descriptor()?;
+        let begin = desc.alloc_tab_begin.0;
+        let old_size = desc.alloc_tab_size;
+        let new_size = old_size.checked_mul(2).ok_or(crate::io::Error::NoSpace)?.max(1024);
+
+        let mut table = self.read_alloc_table()?;
+        let volsize = self.stream.stream_length()?.sector;
+
+        let old_sectors = self.alloc_table_sectors()?;
+        let own = table.iter().position(|span| span.extent!=0 && (span.base_sector, span.base_sector+(span.extent as u128))==old_sectors);
+
+        let old_end = begin+old_size;
+        let new_end = begin+new_size;
+        let extra = (((old_end>>10) + ((old_end&1023)!=0) as u64) as u128, ((new_end>>10) + ((new_end&1023)!=0) as u64) as u128);
+
+        let in_place = extra.1<=volsize
+            && !table.iter().any(|span| span.extent!=0 && span.base_sector<extra.1 && extra.0<span.base_sector+(span.extent as u128));
+
+        if in_place{
+            self.stream.seek(SeekPos::Start(old_end))?;
+            self.stream.write_zeroes((new_size-old_size) as usize)?;
+
+            self.get_or_read_descriptor()?.alloc_tab_size = new_size;
+
+            if let Some(i) = own{
+                table[i].extent = (extra.1-table[i].base_sector) as u64;
+                self.write_alloc_entry(i, &table[i])?;
+            }else if extra.1>extra.0{
+                let mut table = self.read_alloc_table()?;
+                self.record_reservation(&mut table, extra.0, (extra.1-extra.0) as u64)?;
+            }
+        }else{
+            let new_sectors = (new_size>>10) + ((new_size&1023)!=0) as u64;
+            let base = self.find_free_run(&table, new_sectors)?;
+
+            self.stream.seek(SeekPos::StartSector(base))?;
+            self.stream.write_all(bytemuck::cast_slice(&table))?;
+            self.stream.write_zeroes(((new_sectors<<10)-old_size) as usize)?;
+
+            let desc = self.get_or_read_descriptor()?;
+            desc.alloc_tab_begin = AbsPos((base<<10) as u64);
+            desc.alloc_tab_size = new_size;
+
+            // The slots after the copied entries are all unused
+            self.write_alloc_entry(table.len(), &VolumeSpan{base_sector: base, extent: new_sectors, __reserved: 0})?;
+
+            if let Some(i) = own{
+                table[i] = Zeroable::zeroed();
+                self.write_alloc_entry(i, &table[i])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reserves `extent` sectors starting exactly at `base`, if that range is entirely free.
+    ///
+    /// Returns `Ok(false)` without modifying the allocation table if any part of the range is already reserved or lies outside of the volume.
+    pub fn try_reserve_at(&mut self, base: SectorPos, extent: u64) -> crate::io::Result<bool>{
+        if extent==0{
+            return Err(crate::io::Error::InvalidInput)
+        }
+
+        let base = base.0;
+        let end = base+(extent as u128);
+
+        self.ensure_alloc_slot()?;
+
+        let mut table = self.read_alloc_table()?;
+        let volsize = self.stream.stream_length()?.sector;
+
+        if end>volsize || table.iter().any(|span| span.extent!=0 && span.base_sector<end && base<span.base_sector+(span.extent as u128)){
+            return Ok(false)
+        }
+
+        self.record_reservation(&mut table, base, extent)?;
+
+        Ok(true)
+    }
+
+    /// Releases `extent` sectors starting at `base`, which were previously reserved by [`FilesystemAccess::allocate_contiguous_space`].
+    ///
+    /// Adjacent spans which together cover the range are merged before the range is removed, and a span is split if the range is in the middle of it.
+    ///
+    /// Returns [`Error::DoubleFree`][crate::io::Error::DoubleFree] if any part of the range is not currently reserved,
+    ///  or [`Error::NoSpace`][crate::io::Error::NoSpace] if the allocation table is full and cannot be grown.
+    pub fn free_space(&mut self, base: SectorPos, extent: u64) -> crate::io::Result<()>{
+        if extent==0{
+            return Err(crate::io::Error::InvalidInput)
+        }
+
+        let base = base.0;
+        let end = base+(extent as u128);
+
+        self.ensure_alloc_slot()?;
+
+        let mut table = self.read_alloc_table()?;
+
+        let mut order: Vec<usize> = (0..table.len()).filter(|&i| table[i].extent!=0).collect();
+        order.sort_by_key(|&i| table[i].base_sector);
+
+        let span_end = |span: &VolumeSpan| span.base_sector+(span.extent as u128);
+
+        let pos = order.iter()
+            .position(|&i| table[i].base_sector<=base && base<span_end(&table[i]))
+            .ok_or(crate::io::Error::DoubleFree)?;
+
+        let cur = order[pos];
+        let mut dirty = alloc::vec![cur];
+
+        for &i in &order[(pos+1)..]{
+            if span_end(&table[cur])>=end || table[i].base_sector!=span_end(&table[cur]){
+                break;
+            }
+            table[cur].extent += table[i].extent;
+            table[i] = Zeroable::zeroed();
+            dirty.push(i);
+        }
+
+        let span = table[cur];
+
+        if span_end(&span)<end{
+            return Err(crate::io::Error::DoubleFree)
+        }
+
+        match (span.base_sector==base, span_end(&span)==end){
+            (true, true) => table[cur] = Zeroable::zeroed(),
+            (true, false) => {
+                table[cur].base_sector = end;
+                table[cur].extent -= extent;
+            }
+            (false, true) => table[cur].extent -= extent,
+            (false, false) => {
+                // Slots freed by merging above are still available here
+                let slot = table.iter().position(|span| span.extent==0).ok_or(crate::io::Error::NoSpace)?;
+                table[cur].extent = (base-span.base_sector) as u64;
+                table[slot] = VolumeSpan{base_sector: end, extent: (span_end(&span)-end) as u64, __reserved: 0};
+                dirty.push(slot);
+            }
+        }
+
+        for i in dirty{
+            self.write_alloc_entry(i, &table[i])?;
+        }
+
+        Ok(())
+    }
+    /// Formats the stream as a new PhantomFS volume of `volsize` sectors, with the given `label` and volume `id`, using the default [`FormatOptions`].
+    ///
+    /// See [`FilesystemAccess::format`].
+    pub fn create_filesystem(&mut self, label: &str, id: Uuid, volsize: u128) -> crate::io::Result<()>{
+        self.format(label, id, volsize, FormatOptions::new())
+    }
+
+    /// Formats the stream as a new PhantomFS volume of `volsize` sectors, with the given `label` and volume `id`.
+    ///
+    /// The volume contains an empty root directory owned by [`FormatOptions::root_owner`], and the root descriptor is written to the volume before returning.
+    /// The reserved boot sectors, the allocation table, and the object table are all recorded as reserved in the allocation table.
+    ///
+    /// The stream is extended to `volsize` sectors if it is shorter. Since space is allocated up to the end of the stream, the stream must not be longer than the volume.
+    ///
+    /// Returns [`Error::InvalidInput`][crate::io::Error::InvalidInput] if `options` does not describe a valid layout for a volume of `volsize` sectors,
+    ///  or if the stream is longer than `volsize` sectors.
+    pub fn format(&mut self, label: &str, id: Uuid, volsize: u128, options: FormatOptions) -> crate::io::Result<()>{
+        let objtab_sectors = (options.objtab_size>>10) + ((options.objtab_size&1023)!=0) as u64;
+        let alloc_tab_end = options.alloc_tab_begin.0+options.alloc_tab_size;
+        let alloc_tab_sectors = (options.alloc_tab_begin.0>>10, (alloc_tab_end>>10) + ((alloc_tab_end&1023)!=0) as u64);
+
+        if options.boot_sectors<2
+            || options.objtab_size==0 || options.objtab_size%(size_of::<Object>() as u64)!=0
+            || options.alloc_tab_size==0 || options.alloc_tab_size%(size_of::<VolumeSpan>() as u64)!=0
+            || options.alloc_tab_begin.0<2048
+            || volsize<(options.boot_sectors as u128)+(objtab_sectors as u128)
+            || (alloc_tab_sectors.1 as u128)>volsize-(objtab_sectors as u128){
+            return Err(crate::io::Error::InvalidInput)
+        }
+
+        let len = self.stream.stream_length()?;
+
+        if len.sector>volsize || (len.sector==volsize && len.offset!=0){
+            return Err(crate::io::Error::InvalidInput)
+        }
+
+        let objtab_base = volsize-(objtab_sectors as u128);
+
+        let desc = RootDescriptor{
+            magic: PhantomFSMagic::MAGIC,
+            version_major: consts::VERSION_MAJOR,
+            version_minor: consts::VERSION_MINOR,
+            required_features: options.required_features,
+            optional_features: options.optional_features,
+            volume_id_hi: id.hi,
+            volume_id_lo: id.lo,
+            root_object_id: None,
+            objtab_end: SectorPos(volsize),
+            objtab_size: options.objtab_size,
+            alloc_tab_begin: options.alloc_tab_begin,
+            alloc_tab_size: options.alloc_tab_size,
+            label_ref: None,
+            label: Zeroable::zeroed(),
+            header_size: core::mem::size_of::<RootDescriptor>() as u32,
+            crc: 0,
+        };
+
+        self.stream.seek(crate::io::SeekPos::StartSector(objtab_base))?;
+        self.stream.write_zeroes((objtab_sectors<<10) as usize)?;
+
+        self.stream.seek(crate::io::SeekPos::StartSector(1))?;
+        self.stream.write_zeroes(1024)?;
+
+        self.stream.seek(crate::io::SeekPos::Start(options.alloc_tab_begin.0))?;
+        self.stream.write_zeroes(options.alloc_tab_size as usize)?;
+
+        self.root_desc = Some(desc);
+
+        let mut reserved = self.try_reserve_at(SectorPos(0), options.boot_sectors)?;
+
+        if alloc_tab_sectors.1>options.boot_sectors{
+            let begin = alloc_tab_sectors.0.max(options.boot_sectors);
+            reserved &= self.try_reserve_at(SectorPos(begin as u128), alloc_tab_sectors.1-begin)?;
+        }
+
+        reserved &= self.try_reserve_at(SectorPos(objtab_base), objtab_sectors)?;
+
+        if !reserved{
+            return Err(crate::io::Error::InvalidInput)
+        }
+
+        let root = self.create_object(0, ObjectType::Directory, "", options.root_owner)?;
+        self.get_or_read_descriptor()?.root_object_id = Some(root);
+
+        self.set_label(label)?;
+
+        self.sync()
+    }
+}
+
+/// Options which control the layout of a volume created by [`FilesystemAccess::format`].
+///
+/// The defaults, returned by [`FormatOptions::new`], match the layout produced by [`FilesystemAccess::create_filesystem`].
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct FormatOptions{
+    objtab_size: u64,
+    alloc_tab_begin: AbsPos,
+    alloc_tab_size: u64,
+    boot_sectors: u64,
+    required_features: FSRequiredFeatures,
+    optional_features: FSOptionalFeatures,
+    root_owner: Uuid,
+}
+
+impl FormatOptions{
+    /// Returns the default options: a 1024 byte object table, a 1024 byte allocation table in sector 2, 8 reserved boot sectors, no features, and a root directory owned by the system principal.
+    pub const fn new() -> Self{
+        Self{
+            objtab_size: 1024,
+            alloc_tab_begin: AbsPos(2048),
+            alloc_tab_size: 1024,
+            boot_sectors: 8,
+            required_features: FSRequiredFeatures::empty(),
+            optional_features: FSOptionalFeatures::empty(),
+            root_owner: Uuid{lo: consts::SYSTEM_PRINCIPAL as u64, hi: (consts::SYSTEM_PRINCIPAL>>64) as u64},
+        }
+    }
+
+    /// Sets the initial size of the object table, in bytes. This must be a nonzero multiple of the size of [`Object`].
+    pub const fn objtab_size(mut self, size: u64) -> Self{
+        self.objtab_size = size;
+        self
+    }
+
+    /// Sets the byte position of the allocation table, which must not overlap the first two sectors.
+    pub const fn alloc_tab_begin(mut self, begin: AbsPos) -> Self{
+        self.alloc_tab_begin = begin;
+        self
+    }
+
+    /// Sets the size of the allocation table, in bytes. This must be a nonzero multiple of the size of [`VolumeSpan`].
+    pub const fn alloc_tab_size(mut self, size: u64) -> Self{
+        self.alloc_tab_size = size;
+        self
+    }
+
+    /// Sets the number of sectors reserved at the start of the volume, which includes the root descriptor. This must be at least 2.
+    pub const fn boot_sectors(mut self, sectors: u64) -> Self{
+        self.boot_sectors = sectors;
+        self
+    }
+
+    /// Sets the features which an implementation must support to access the volume.
+    pub const fn required_features(mut self, features: FSRequiredFeatures) -> Self{
+        self.required_features = features;
+        self
+    }
+
+    /// Sets the features which the volume uses, but which an implementation may ignore.
+    pub const fn optional_features(mut self, features: FSOptionalFeatures) -> Self{
+        self.optional_features = features;
+        self
+    }
+
+    /// Sets the principal which is granted ObjectOwner on the root directory.
+    pub const fn root_owner(mut self, owner: Uuid) -> Self{
+        self.root_owner = owner;
+        self
+    }
+}
+
+impl Default for FormatOptions{
+    fn default() -> Self{
+        Self::new()
+    }
+}
+
+impl<S: Read + Seek> FilesystemAccess<S>{
+    pub fn get_or_read_descriptor(&mut self) -> crate::io::Result<&mut RootDescriptor>{
+        if let Some(desc) = self.root_desc.as_mut(){
+            Ok(unsafe{&mut *(desc as *mut RootDescriptor)}) // Hecking NLL get_or_insert_with
+        }else{
+
+            let mut root_desc: RootDescriptor = Zeroable::zeroed();
+
+            self.stream.seek(crate::io::SeekPos::StartSector(1))?;
+
+            self.stream.read_fully(bytemuck::bytes_of_mut(&mut root_desc))?;
+
+
+            if root_desc.magic!=PhantomFSMagic::MAGIC{
+                return Err(crate::io::Error::InvalidData);
+            }
+
+            if root_desc.version_major!=consts::VERSION_MAJOR{
+                return Err(crate::io::Error::InvalidData);
+            }
+
+            if root_desc.header_size<(size_of::<RootDescriptor>() as u32){
+                return Err(crate::io::Error::InvalidData);
+            }
+
+            if root_desc.crc != descriptor_crc(&root_desc){
+                return Err(crate::io::Error::InvalidData);
+            }
+
+            self.root_desc = Some(root_desc);
+
+            Ok(self.root_desc.as_mut().unwrap())
+        }
+    }
+
+
+    /// Returns the volume label.
+    ///
+    /// Labels which fit are stored inline in the root descriptor, and longer labels are stored in the Strings stream of the root object, referred to by `label_ref`.
+    pub fn label(&mut self) -> crate::io::Result<String>{
+        if let Some(label) = &self.label{
+            return Ok(label.clone())
+        }
+
+        let desc = *self.get_or_read_descriptor()?;
+
+        let label = if let Some(label_ref) = desc.label_ref{
+            let root = desc.root_object_id.ok_or(crate::io::Error::InvalidData)?;
+
+            self.get_string(root, label_ref)?
+        }else{
+            let label = desc.label.split(|n|*n==0).next().unwrap();
+
+            String::from_utf8(label.to_vec()).map_err(|_|crate::io::Error::InvalidData)?
+        };
+
+        self.label = Some(label.clone());
+
+        Ok(label)
+    }
+
+    pub fn get_obj_by_id(&mut self, id: ObjectId) -> crate::io::Result<Object>{
+        let desc = self.get_or_read_descriptor()?;
+        let objtab_end = desc.objtab_end;
+        let objtabsize = desc.objtab_size;
+
+        let pos = id.0.get()*(size_of::<Object>() as u64);
+
+        if pos>objtabsize{
+            return Err(crate::io::Error::NotFound);
+        }
+
+        self.stream.seek(SeekPos::StartSector(objtab_end.0))?;
+        self.stream.seek(SeekPos::Curr(-(pos as i64)))?;
+
+        let mut obj: Object = Zeroable::zeroed();
+
+        self.stream.read_fully(bytemuck::bytes_of_mut(&mut obj))?;
+
+        if obj.weak_ref==0{
+            // This is an invalid object, so for all intents and purposes, it does not exist
+            return Err(crate::io::Error::NotFound)
+        }
+
+        Ok(obj)
+    }
+
+    fn read_span(&mut self, block: u128, idx: u64) -> crate::io::Result<VolumeSpan>{
+        let mut span: VolumeSpan = Zeroable::zeroed();
+
+        self.stream.seek(SeekPos::StartSector(block))?;
+        self.stream.seek(SeekPos::Curr((idx*(size_of::<VolumeSpan>() as u64)) as i64))?;
+        self.stream.read_fully(bytemuck::bytes_of_mut(&mut span))?;
+
+        Ok(span)
+    }
+
+    /// Finds `offset` in the index block at `block`, which is `sectors` long and has `level` levels of indirection below it.
+    ///
+    /// Entries in a block with 2 levels of indirection are data extents, and entries in blocks with more levels refer to index blocks with one less level.
+    /// Each block ends at its first entry with an `extent` of 0.
+    fn locate_in_block(&mut self, block: u128, sectors: u64, level: u8, mut offset: u64) -> crate::io::Result<Located>{
+        let mut skipped = 0;
+
+        for idx in 0..(sectors*((1024/size_of::<VolumeSpan>()) as u64)){
+            let span = self.read_span(block, idx)?;
+
+            if span.extent==0{
+                break;
+            }
+
+            let bytes = if level==2{
+                let bytes = span.extent<<10;
+
+                if offset<bytes{
+                    return Ok(Located::Found{sector: span.base_sector, offset, avail: bytes-offset})
+                }
+                bytes
+            }else{
+                match self.locate_in_block(span.base_sector, span.extent, level-1, offset)?{
+                    Located::Skipped(bytes) => bytes,
+                    found => return Ok(found)
+                }
+            };
+
+            offset -= bytes;
+            skipped += bytes;
+        }
+
+        Ok(Located::Skipped(skipped))
+    }
+
+    /// Finds the sector containing `offset` in the stream content at `baseref` with the given level of `indirection`, and the offset into that sector.
+    ///
+    /// Also returns the number of bytes which are stored contiguously from that position, which is limited to the remaining length of the stream.
+    /// Returns [`None`] if `offset` is not before the end of the stream.
+    fn locate_by_indirection(&mut self, offset: u64, baseref: u128, indirection: u8, len: u64) -> crate::io::Result<Option<(u128, u64, u64)>>{
+        if offset>=len{
+            return Ok(None)
+        }
+
+        let located = if indirection==1{
+            Located::Found{sector: baseref, offset, avail: len-offset}
+        }else{
+            self.locate_in_block(baseref, 1, indirection, offset)?
+        };
+
+        match located{
+            Located::Found{sector, offset: inner, avail} => Ok(Some((sector+((inner>>10) as u128), inner&1023, avail.min(len-offset)))),
+            Located::Skipped(_) => Err(crate::io::Error::InvalidData),
+        }
+    }
+
+    fn read_by_indirection(&mut self, offset: u64,buf: &mut [u8], baseref: u128, indirection: u8,len: u64) -> crate::io::Result<usize>{
+        match self.locate_by_indirection(offset, baseref, indirection, len)?{
+            Some((sector, inner, avail)) => {
+                let len = (buf.len() as u64).min(avail) as usize;
+
+                self.stream.seek(SeekPos::StartSector(sector))?;
+                self.stream.seek(SeekPos::Curr(inner as i64))?;
+                self.stream.read(&mut buf[..len])
+            }
+            None => Ok(0)
+        }
+    }
+
+    fn read_fully_by_indirection(&mut self, mut offset: u64,mut buf: &mut [u8], baseref: u128, indirection: u8,len: u64) -> crate::io::Result<()>{
+        if indirection==0{
+            panic!("read_by_indirection does not support inline data, handle via appropriate top-level type instead")
+        }
+
+        while !buf.is_empty(){
+            match self.read_by_indirection(offset, buf, baseref, indirection,len){
+                Ok(0) => return Err(crate::io::Error::UnexpectedEof),
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(crate::io::Error::Interrupted) => {}
+                Err(e) => return Err(e)
+             }
+        }
+        Ok(())
+    }
+
+    fn read_nullstr_by_indirection(&mut self, mut offset: u64, baseref: u128, indirection: u8, len: u64) -> crate::io::Result<String>{
+        let mut str = Vec::new();
+        loop{
+            let mut buf = [0;1024];
+            match self.read_by_indirection(offset, &mut buf, baseref, indirection, len){
+                Ok(0) => return Err(crate::io::Error::UnexpectedEof),
+                Ok(n) => {
+                    let buf = &buf[..n];
+
+                    for b in buf{
+                        if *b==0{
+                            return String::from_utf8(str).map_err(|_|crate::io::Error::InvalidData)
+                        }
+                        str.push(*b);
+                    }
+
+                    offset += n as u64;
+                }
+                Err(crate::io::Error::Interrupted) => continue,
+                Err(e) => return Err(e)
+            }
+        }
+    }
+
+    fn cmp_nullstr_by_indirection(&mut self, name: &str, mut offset: u64, baseref: u128, indirection: u8, len: u64) -> crate::io::Result<Ordering>{
+        let mut name = name.as_bytes();
+        loop{
+            let mut buf = [0;1024];
+            match self.read_by_indirection(offset, &mut buf, baseref, indirection, len){
+                Ok(0) => return Err(crate::io::Error::UnexpectedEof),
+                Ok(n) => {
+                    let buf = &buf[..n];
+
+                    for (idx,b) in buf.iter().enumerate(){
+                        if *b==0{
+                            return Ok(idx.cmp(&name.len()))
+                        }
+                        else if name.len()==idx{
+                            return Ok(Ordering::Greater);
+                        }else{
+                            match b.cmp(&name[idx]){
+                                Ordering::Equal => continue,
+                                o => return Ok(o)
+                            }
+                        }
+                    }
+                    name = &name[n..];
+                    offset += n as u64;
+                }
+                Err(crate::io::Error::Interrupted) => continue,
+                Err(e) => return Err(e)
+            }
+        }
+    }
+
+    fn cmp_nullstr_from_stream(&mut self, str: &str, pos: u64, stream: &StreamListing) -> crate::io::Result<Ordering>{
+        
+
+        if pos>=stream.size{
+            return Err(crate::io::Error::UnexpectedEof)
+        }
+
+        let indirection = stream.flags.get_indirection() as u8;
+
+
+        if indirection==0{
+            let str = str.as_bytes();
+            let base = &stream.inline_data[(pos as usize)..(stream.size as usize)];
+
+            let content = base.split(|n|*n==0).next().unwrap();
+
+            Ok(content.cmp(str))
+        }else{
+            self.cmp_nullstr_by_indirection(str, pos, stream.content_ref, indirection, stream.size)
+        }
+    }
+
+    fn read_nullstr_from_stream(&mut self, pos: u64, stream: &StreamListing) -> crate::io::Result<String>{
+        if pos>=stream.size{
+            return Err(crate::io::Error::UnexpectedEof)
+        }
+
+        let indirection = stream.flags.get_indirection() as u8;
+
+        if indirection==0{
+            let content = stream.inline_data[(pos as usize)..(stream.size as usize)].split(|n|*n==0).next().unwrap();
+
+            String::from_utf8(content.to_vec()).map_err(|_|crate::io::Error::InvalidData)
+        }else{
+            self.read_nullstr_by_indirection(pos, stream.content_ref, indirection, stream.size)
+        }
+    }
+
+    /// Reads the string at offset `idx` of the Strings stream of `objid`, such as one returned by [`FilesystemAccess::intern_string`].
+    pub fn get_string(&mut self, objid: ObjectId, idx: NonZeroU64) -> crate::io::Result<String>{
+        let obj = self.get_obj_by_id(objid)?;
+        let strings = self.get_stream_by_id(objid, StreamId(obj.strings_stream.ok_or(crate::io::Error::NotFound)?.get()))?;
+
+        self.read_nullstr_from_stream(idx.get(), &strings)
+    }
+
+    pub fn read_from_stream(&mut self, buf: &mut [u8], pos: u64, stream: &StreamListing) -> crate::io::Result<usize>{
+        let max_len = buf.len().min(stream.size.saturating_sub(pos) as usize);
+
+        let buf = &mut buf[..max_len];
+
+        let indirection = stream.flags.get_indirection() as u8;
+
+        if max_len==0{
+            Ok(0)
+        }else if indirection ==0{
+            buf.copy_from_slice(&stream.inline_data[(pos as usize)..][..max_len]);
+
+            Ok(max_len)
+        }else{
+            self.read_by_indirection(pos, buf, stream.content_ref, indirection, stream.size)
+        }
+    }
+
+    pub fn read_fully_from_stream(&mut self, buf: &mut [u8], pos: u64, stream: &StreamListing) -> crate::io::Result<()>{
+
+        if (stream.size.saturating_sub(pos) as usize)<buf.len(){
+            return Err(crate::io::Error::UnexpectedEof)
+        }
+
+        let indirection = stream.flags.get_indirection() as u8;
+        if indirection==0{
+            buf.copy_from_slice(&stream.inline_data[(pos as usize)..][..buf.len()]);
+
+            Ok(())
+        }else{
+            self.read_fully_by_indirection(pos, buf, stream.content_ref, indirection, stream.size)
+        }
+    }
+
+    /// Opens the stream of `objid` named `stream`, returning a handle which reads and writes from its own position, starting at the beginning of the stream.
+    pub fn open_stream(&mut self, objid: ObjectId, stream: &str) -> crate::io::Result<StreamHandle<'_, S>>{
+        let (id, listing) = self.find_stream_by_id(objid, stream)?;
+
+        Ok(StreamHandle{fs: self, objid, id, listing, pos: 0})
+    }
+
+    pub fn get_stream_by_id(&mut self, objid: ObjectId, stream: StreamId) -> crate::io::Result<StreamListing>{
+        let obj = self.get_obj_by_id(objid)?;
+
+        if obj.strong_ref==0{
+            // Note: weak_ref is tested by `get_obj_by_id`. Otherwise, condition should test weak_ref as well, since strong_ref has undefined content with weak_ref==0
+            return Err(crate::io::Error::NotFound)
+        }
+
+        let pos = stream.0*(size_of::<StreamListing>() as u64);
+
+        if pos>=obj.streams_size{
+            return Err(crate::io::Error::NotFound)
+        }
+
+        let mut listing = Zeroable::zeroed();
+
+        self.read_fully_by_indirection(pos, bytemuck::bytes_of_mut(&mut listing), obj.streams_ref,obj.streams_indirection,obj.streams_size)?;
+
+        Ok(listing)
+    }
+
+    pub fn find_stream_by_id(&mut self, objid: ObjectId, stream: &str) -> crate::io::Result<(StreamId,StreamListing)>{
+        let mut idx = 0u64;
+
+        let obj = self.get_obj_by_id(objid)?;
+        
+        let strings_stream = obj.strings_stream.map(|id| self.get_stream_by_id(objid,StreamId(id.get()))).transpose()?;
+
+
+        while (idx*(size_of::<StreamListing>() as u64))<obj.streams_size{
+            let mut listing: StreamListing = Zeroable::zeroed();
+            self.read_fully_by_indirection(idx*(size_of::<StreamListing>() as u64), bytemuck::bytes_of_mut(&mut listing), obj.streams_ref,obj.streams_indirection,obj.streams_size)?;
+
+            if let Some(nref) = listing.name_ref{
+                if let Some(strings) = &strings_stream{
+                    if self.cmp_nullstr_from_stream(stream, nref.get(), strings)?==Ordering::Equal{
+                        return Ok((StreamId(idx),listing));
+                    }
+                }
+            }else{
+                let name = listing.name.split(|f|*f==0).next().unwrap();
+
+                if stream.as_bytes()==name{
+                    return Ok((StreamId(idx),listing));
+                }
+            }
+            idx+=1;
+        }
+        
+        Err(crate::io::Error::NotFound)
+    }
+
+
+    /// Finds the entry named `subfilename` in the directory `objid`, and returns its slot in the DirectoryContent stream along with the entry.
+    fn find_dir_entry(&mut self, objid: ObjectId, subfilename: &str) -> crate::io::Result<(u64, DirectoryElement)>{
+        let obj = self.get_obj_by_id(objid)?;
+
+        let (_,stream) = self.find_stream_by_id(objid, consts::DIRECTORYCONTENT_STREAM)?;
+
+        let strings_stream = obj.strings_stream.map(|id| self.get_stream_by_id(objid,StreamId(id.get()))).transpose()?;
+
+        let len = stream.size/(size_of::<DirectoryElement>() as u64);
+        for i in 0..len{
+            let mut element: DirectoryElement = Zeroable::zeroed();
+
+            self.read_from_stream(bytemuck::bytes_of_mut(&mut element), i*(size_of::<DirectoryElement>() as u64), &stream)?;
+
+            if let Some(nameref) = element.name_index{
+                if let Some(strings) = &strings_stream{
+                    if self.cmp_nullstr_from_stream(subfilename, nameref.get(), strings)?.is_eq(){
+                        return Ok((i, element));
+                    }
+                }
+            }else{
+                let name = element.name.split(|f|*f==0).next().unwrap();
+
+                if subfilename.as_bytes()==name{
+                    return Ok((i, element));
+                }
+            }
+        }
+
+
+        Err(crate::io::Error::NotFound)
+    }
+
+    pub fn search_directory(&mut self, objid: ObjectId, subfilename: &str) -> crate::io::Result<ObjectId>{
+        let (_, element) = self.find_dir_entry(objid, subfilename)?;
+
+        element.objidx.ok_or(crate::io::Error::NotFound)
+    }
+
+    /// Returns an iterator over the entries of the directory `objid`, in the order they appear in its DirectoryContent stream.
+    ///
+    /// Removed entries are skipped. Entries with the `HIDDEN` flag are included unless [`ReadDir::skip_hidden`] is used.
+    pub fn read_dir(&mut self, objid: ObjectId) -> crate::io::Result<ReadDir<'_, S>>{
+        let obj = self.get_obj_by_id(objid)?;
+
+        if obj.ty!=ObjectType::Directory{
+            return Err(crate::io::Error::InvalidInput)
+        }
+
+        let (_,stream) = self.find_stream_by_id(objid, consts::DIRECTORYCONTENT_STREAM)?;
+
+        let strings = obj.strings_stream.map(|id| self.get_stream_by_id(objid,StreamId(id.get()))).transpose()?;
+
+        Ok(ReadDir{fs: self, stream, strings, slot: 0, skip_hidden: false})
+    }
+
+    /// Walks `path` one component at a time, starting from the directory at the top of `stack`, and pushes each directory entered onto `stack`.
+    ///
+    /// An absolute path restarts from the root object. Empty components and `.` are ignored, and `..` pops the stack.
+    /// There is no parent pointer on disk, so `..` in the bottom-most directory of `stack` only succeeds if that directory is the root object, which is its own parent.
+    ///
+    /// If `follow` is `Some`, each symlink encountered is replaced by its target, resolved relative to the directory containing the symlink.
+    /// The count is decremented for each symlink followed, and [`Error::SymlinkLoop`][crate::io::Error::SymlinkLoop] is returned if it reaches zero.
+    fn walk_path(&mut self, stack: &mut Vec<ObjectId>, path: &str, follow: &mut Option<u32>) -> crate::io::Result<()>{
+        let root = self.get_or_read_descriptor()?.root_object_id.ok_or(crate::io::Error::InvalidData)?;
+
+        if path.starts_with('/'){
+            stack.clear();
+            stack.push(root);
+        }
+
+        for component in path.split('/'){
+            let cur = *stack.last().ok_or(crate::io::Error::InvalidInput)?;
+            match component{
+                "" => {},
+                "." => {
+                    if self.get_obj_by_id(cur)?.ty!=ObjectType::Directory{
+                        return Err(crate::io::Error::InvalidInput)
+                    }
+                },
+                ".." => {
+                    if self.get_obj_by_id(cur)?.ty!=ObjectType::Directory{
+                        return Err(crate::io::Error::InvalidInput)
+                    }
+                    if stack.len()>1{
+                        stack.pop();
+                    }else if cur!=root{
+                        return Err(crate::io::Error::InvalidInput)
+                    }
+                },
+                name => {
+                    if self.get_obj_by_id(cur)?.ty!=ObjectType::Directory{
+                        return Err(crate::io::Error::InvalidInput)
+                    }
+                    let next = self.search_directory(cur, name)?;
+
+                    match follow{
+                        Some(remaining) if self.get_obj_by_id(next)?.ty==ObjectType::Symlink => {
+                            if *remaining==0{
+                                return Err(crate::io::Error::SymlinkLoop)
+                            }
+                            *remaining -= 1;
+
+                            let target = self.read_link(next)?;
+                            self.walk_path(stack, &target, follow)?;
+                        }
+                        _ => stack.push(next)
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolves `path` from the root object. Relative paths are treated the same as absolute paths.
+    pub fn resolve_path(&mut self, path: &str) -> crate::io::Result<ObjectId>{
+        let root = self.get_or_read_descriptor()?.root_object_id.ok_or(crate::io::Error::InvalidData)?;
+
+        self.resolve_path_from(root, &[], path)
+    }
+
+    /// Resolves `path` relative to the directory `dir`, or from the root object if `path` is absolute.
+    ///
+    /// `ancestors` lists the directories containing `dir`, starting from the outermost, so that the last is the directory which contains `dir`, as for [`FilesystemAccess::check_access_inherited`].
+    /// The volume does not record parent directories, so `..` moves through `ancestors`. It cannot leave the outermost directory (or `dir`, if `ancestors` is empty) unless that is the root object.
+    pub fn resolve_path_from(&mut self, dir: ObjectId, ancestors: &[ObjectId], path: &str) -> crate::io::Result<ObjectId>{
+        let mut stack = ancestors.to_vec();
+        stack.push(dir);
+
+        self.walk_path(&mut stack, path, &mut None)?;
+
+        Ok(*stack.last().unwrap())
+    }
+
+    /// Resolves `path` as by [`FilesystemAccess::resolve_path_from`], following every symlink encountered, including the last component of `path`.
+    ///
+    /// At most `max_symlinks` symlinks are followed. If more are encountered, such as because of a loop, [`Error::SymlinkLoop`][crate::io::Error::SymlinkLoop] is returned.
+    pub fn resolve_path_follow(&mut self, dir: ObjectId, ancestors: &[ObjectId], path: &str, max_symlinks: u32) -> crate::io::Result<ObjectId>{
+        let mut stack = ancestors.to_vec();
+        stack.push(dir);
+
+        self.walk_path(&mut stack, path, &mut Some(max_symlinks))?;
+
+        Ok(*stack.last().unwrap())
+    }
+
+    /// Reads the whole of stream `name` of `objid` as a `T`. Returns [`Error::InvalidData`][crate::io::Error::InvalidData] if the stream is not exactly the size of `T`.
+    fn read_stream_as<T: Pod>(&mut self, objid: ObjectId, name: &str) -> crate::io::Result<T>{
+        let (_, stream) = self.find_stream_by_id(objid, name)?;
+
+        if stream.size!=(size_of::<T>() as u64){
+            return Err(crate::io::Error::InvalidData)
+        }
+
+        let mut val: T = Zeroable::zeroed();
+        self.read_fully_from_stream(bytemuck::bytes_of_mut(&mut val), 0, &stream)?;
+
+        Ok(val)
+    }
+
+    /// Reads the [`DeviceId`] of the block or character device `objid`.
+    pub fn device_id(&mut self, objid: ObjectId) -> crate::io::Result<DeviceId>{
+        let ty = self.get_obj_by_id(objid)?.ty;
+        if ty!=ObjectType::BlockDevice && ty!=ObjectType::CharDevice{
+            return Err(crate::io::Error::InvalidInput)
+        }
+
+        self.read_stream_as(objid, consts::DEVICEID_STREAM)
+    }
+
+    /// Reads the [`LegacyDeviceNumber`] of the block or character device `objid`, or `None` if it does not have one.
+    pub fn legacy_device_number(&mut self, objid: ObjectId) -> crate::io::Result<Option<LegacyDeviceNumber>>{
+        let ty = self.get_obj_by_id(objid)?.ty;
+        if ty!=ObjectType::BlockDevice && ty!=ObjectType::CharDevice{
+            return Err(crate::io::Error::InvalidInput)
+        }
+
+        match self.read_stream_as(objid, consts::LEGACYDEVICENUMBER_STREAM){
+            Ok(legacy) => Ok(Some(legacy)),
+            Err(crate::io::Error::NotFound) => Ok(None),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Reads the POSIX owner, group, and mode of `objid` from its LegacySecurityDescriptor stream, or `None` if it does not have one.
+    pub fn get_posix_attrs(&mut self, objid: ObjectId) -> crate::io::Result<Option<LegacySecurityDescriptor>>{
+        match self.read_stream_as(objid, consts::LEGACYSECURITYDESCRIPTOR_STREAM){
+            Ok(attrs) => Ok(Some(attrs)),
+            Err(crate::io::Error::NotFound) => Ok(None),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Reads the target of the symlink `objid`.
+    pub fn read_link(&mut self, objid: ObjectId) -> crate::io::Result<String>{
+        if self.get_obj_by_id(objid)?.ty!=ObjectType::Symlink{
+            return Err(crate::io::Error::InvalidInput)
+        }
+
+        let (_, stream) = self.find_stream_by_id(objid, consts::SYMLINKTARGET_STREAM)?;
+
+        let mut target = alloc::vec![0u8; stream.size as usize];
+        self.read_fully_from_stream(&mut target, 0, &stream)?;
+
+        String::from_utf8(target).map_err(|_| crate::io::Error::InvalidData)
+    }
+
+    /// Reads every row of the SecurityDescriptor stream of `objid`.
+    fn read_security_rows(&mut self, objid: ObjectId) -> crate::io::Result<Vec<SecurityDescriptorRow>>{
+        let (_, stream) = self.find_stream_by_id(objid, consts::SECURITYDESCRIPTOR_STREAM)?;
+
+        let count = stream.size/(size_of::<SecurityDescriptorRow>() as u64);
+        let mut rows = Vec::with_capacity(count as usize);
+
+        for i in 0..count{
+            let mut row: SecurityDescriptorRow = Zeroable::zeroed();
+            self.read_fully_from_stream(bytemuck::bytes_of_mut(&mut row), i*(size_of::<SecurityDescriptorRow>() as u64), &stream)?;
+            rows.push(row);
+        }
+
+        Ok(rows)
+    }
+
+    /// Checks whether the permission named by `row` is `permission`, resolving `permission_name_ref` through `strings`.
+    fn row_names_permission(&mut self, row: &SecurityDescriptorRow, permission: &str, strings: Option<&StreamListing>) -> crate::io::Result<bool>{
+        if let Some(nameref) = row.permission_name_ref{
+            match strings{
+                Some(strings) => Ok(self.cmp_nullstr_from_stream(permission, nameref.get(), strings)?.is_eq()),
+                None => Err(crate::io::Error::InvalidData)
+            }
+        }else{
+            let name = row.permission_name.split(|f|*f==0).next().unwrap();
+
+            Ok(name==permission.as_bytes())
+        }
+    }
+
+    /// Finds the row of `objid` which decides whether `principal` has `permission`, or `None` if no row applies, and whether any Inherit row applies.
+    ///
+    /// A row applies if its principal is `principal` or [`consts::DEFAULT_PRINCIPAL`], its permission is `permission` or [`consts::ALL_PERMISSIONS`],
+    ///  and it is for the whole object or for `stream`. Of the rows which apply, the first Forbid row decides, then the first Deny row, then the first Permit row.
+    ///
+    /// Returns [`Error::Unsupported`][crate::io::Error::Unsupported] if a row which applies has an unknown mode and the `REQUIRED` flag. Unknown rows without the flag are ignored.
+    fn find_deciding_row(&mut self, objid: ObjectId, principal: u128, permission: &str, stream: Option<StreamId>) -> crate::io::Result<(Option<SecurityDescriptorRow>, bool)>{
+        let obj = self.get_obj_by_id(objid)?;
+        let strings = obj.strings_stream.map(|id| self.get_stream_by_id(objid,StreamId(id.get()))).transpose()?;
+
+        let mut decided: Option<SecurityDescriptorRow> = None;
+        let mut inherits = false;
+
+        for row in self.read_security_rows(objid)?{
+            if row.principal!=principal && row.principal!=consts::DEFAULT_PRINCIPAL{
+                continue;
+            }
+
+            if row.stream_id!=StreamId::STREAMS && Some(row.stream_id)!=stream{
+                continue;
+            }
+
+            if !self.row_names_permission(&row, permission, strings.as_ref())? && !self.row_names_permission(&row, consts::ALL_PERMISSIONS, strings.as_ref())?{
+                continue;
+            }
+
+            match row.flags_and_mode.mode(){
+                SecurityDescRowMode::Permit | SecurityDescRowMode::Deny | SecurityDescRowMode::Forbid => {},
+                SecurityDescRowMode::Inherit => {
+                    inherits = true;
+                    continue
+                },
+                _ if row.flags_and_mode.contains(SecurityDescRowFlags::REQUIRED) => return Err(crate::io::Error::Unsupported),
+                _ => continue,
+            }
+
+            if decided.map_or(true, |decided| mode_precedence(row.flags_and_mode.mode())>mode_precedence(decided.flags_and_mode.mode())){
+                decided = Some(row);
+            }
+        }
+
+        Ok((decided, inherits))
+    }
+
+    /// Checks whether `principal` has `permission` on `objid`, or on stream `stream` of `objid` if given.
+    ///
+    /// The rows of the SecurityDescriptor stream which apply are those for `principal` or for [`consts::DEFAULT_PRINCIPAL`],
+    ///  which name `permission` or [`consts::ALL_PERMISSIONS`], and which are for the whole object or for `stream`.
+    /// Access is granted only if a Permit row applies, and no Deny or Forbid row does. Inherit rows are not resolved, and have no effect.
+    /// Use [`FilesystemAccess::check_access_inherited`] to resolve them.
+    pub fn check_access(&mut self, objid: ObjectId, principal: u128, permission: &str, stream: Option<StreamId>) -> crate::io::Result<bool>{
+        let (row, _) = self.find_deciding_row(objid, principal, permission, stream)?;
+
+        Ok(matches!(row.map(|row| row.flags_and_mode.mode()), Some(SecurityDescRowMode::Permit)))
+    }
+
+    /// Checks whether `principal` has `permission` on `objid`, or on stream `stream` of `objid` if given, resolving Inherit rows through `ancestors`.
+    ///
+    /// `ancestors` lists the directories containing `objid`, starting from the outermost, so that the last is the directory which contains `objid`. These are not checked to actually contain each other.
+    ///
+    /// The rows of `objid` are evaluated as by [`FilesystemAccess::check_access`]. If an Inherit row applies, the rows of the containing directory for the whole directory are folded in,
+    ///  and so on while the directory has an applicable Inherit row of its own. Forbid, Deny, and Permit rows take precedence in that order, whichever object they come from.
+    /// Between rows of the same mode, the row from the object nearest to `objid` decides.
+    pub fn check_access_inherited(&mut self, objid: ObjectId, ancestors: &[ObjectId], principal: u128, permission: &str, stream: Option<StreamId>) -> crate::io::Result<AccessReport>{
+        let mut decided_by: Option<(ObjectId, SecurityDescriptorRow)> = None;
+
+        let mut parents = ancestors.iter().rev();
+        let mut cur = objid;
+        let mut stream = stream;
+
+        loop{
+            let (row, inherits) = self.find_deciding_row(cur, principal, permission, stream)?;
+
+            if let Some(row) = row{
+                if decided_by.map_or(true, |(_, decided)| mode_precedence(row.flags_and_mode.mode())>mode_precedence(decided.flags_and_mode.mode())){
+                    decided_by = Some((cur, row));
+                }
+            }
+
+            match parents.next(){
+                Some(parent) if inherits => cur = *parent,
+                _ => break
+            }
+            stream = None;
+        }
+
+        let granted = matches!(decided_by.map(|(_, row)| row.flags_and_mode.mode()), Some(SecurityDescRowMode::Permit));
+
+        Ok(AccessReport{granted, decided_by})
+    }
+
+    /// Lists the rows of the SecurityDescriptor stream of `objid`, with permission names resolved.
+    ///
+    /// Returns [`Error::Unsupported`][crate::io::Error::Unsupported] if the stream has a row with an unknown mode and the `REQUIRED` flag.
+    pub fn list_security_rows(&mut self, objid: ObjectId) -> crate::io::Result<Vec<SecurityRow>>{
+        let obj = self.get_obj_by_id(objid)?;
+        let strings = obj.strings_stream.map(|id| self.get_stream_by_id(objid,StreamId(id.get()))).transpose()?;
+
+        let rows = self.read_security_rows(objid)?;
+
+        if !rows.iter().all(row_is_understood){
+            return Err(crate::io::Error::Unsupported)
+        }
+
+        let mut list = Vec::with_capacity(rows.len());
+        for row in rows{
+            let permission = if let Some(nameref) = row.permission_name_ref{
+                let strings = strings.as_ref().ok_or(crate::io::Error::InvalidData)?;
+                self.read_nullstr_from_stream(nameref.get(), strings)?
+            }else{
+                let name = row.permission_name.split(|f|*f==0).next().unwrap();
+                core::str::from_utf8(name).map(String::from).map_err(|_| crate::io::Error::InvalidData)?
+            };
+
+            list.push(SecurityRow{principal: row.principal, stream_id: row.stream_id, flags_and_mode: row.flags_and_mode, permission});
+        }
+
+        Ok(list)
+    }
+
+    /// Computes the permission bits of a POSIX mode from the SecurityDescriptor stream of `objid`, with `owner` and `group` as the principals of the owner and group classes.
+    ///
+    /// This is the reverse of [`LegacySecurityDescriptor::synthesize_rows`]: each bit is set if [`FilesystemAccess::check_access`] grants the corresponding permission on the whole object
+    ///  to `owner`, `group`, or [`consts::DEFAULT_PRINCIPAL`]. Only the bits in [`LegacySecurityDescriptor::PERMISSION_BITS`] are computed.
+    pub fn posix_mode_from_rows(&mut self, objid: ObjectId, owner: u128, group: u128) -> crate::io::Result<u16>{
+        let mut mode = 0;
+
+        for (principal, shift) in [(owner, 6), (group, 3), (consts::DEFAULT_PRINCIPAL, 0)]{
+            for (permission, bit) in [(consts::READ_PERMISSION, 4), (consts::WRITE_PERMISSION, 2), (consts::EXECUTE_PERMISION, 1)]{
+                if self.check_access(objid, principal, permission, None)?{
+                    mode |= bit<<shift;
+                }
+            }
+        }
+
+        Ok(mode)
+    }
+
+    /// Checks whether `principal` has `permission` on the object at `path`, as by [`FilesystemAccess::check_access_inherited`], using the directories along `path` as the ancestors.
+    ///
+    /// `path` is resolved from the root object as by [`FilesystemAccess::resolve_path`], so `..` components are removed before the ancestors are determined.
+    pub fn check_access_path(&mut self, path: &str, principal: u128, permission: &str, stream: Option<StreamId>) -> crate::io::Result<AccessReport>{
+        let root = self.get_or_read_descriptor()?.root_object_id.ok_or(crate::io::Error::InvalidData)?;
+
+        let mut stack = alloc::vec![root];
+        self.walk_path(&mut stack, path, &mut None)?;
+
+        let objid = stack.pop().unwrap();
+
+        self.check_access_inherited(objid, &stack, principal, permission, stream)
+    }
+}
+
+/// The outcome of an access check, as returned by [`FilesystemAccess::check_access_inherited`].
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct AccessReport{
+    /// Whether access is granted
+    pub granted: bool,
+    /// The row which decided the outcome, and the object it belongs to, or `None` if no row applied, in which case access is not granted.
+    pub decided_by: Option<(ObjectId, SecurityDescriptorRow)>,
+}
+
+/// A row of a SecurityDescriptor stream with its permission name resolved, as returned by [`FilesystemAccess::list_security_rows`].
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct SecurityRow{
+    pub principal: u128,
+    pub stream_id: StreamId,
+    pub flags_and_mode: SecurityDescRowFlags,
+    pub permission: String,
+}
+
+/// A handle to a single stream of an object, which tracks its own position in the stream.
+///
+/// Writing past the end of the stream extends it.
+pub struct StreamHandle<'a, S>{
+    fs: &'a mut FilesystemAccess<S>,
+    objid: ObjectId,
+    id: StreamId,
+    listing: StreamListing,
+    pos: u64,
+}
+
+impl<S> StreamHandle<'_, S>{
+    pub fn object_id(&self) -> ObjectId{
+        self.objid
+    }
+
+    pub fn stream_id(&self) -> StreamId{
+        self.id
+    }
+
+    pub fn listing(&self) -> &StreamListing{
+        &self.listing
+    }
+}
+
+impl<S: Read + Write + Seek> StreamHandle<'_, S>{
+    /// Sets the length of the stream, as by [`FilesystemAccess::set_stream_len`]. The position of the handle is unchanged.
+    pub fn set_len(&mut self, len: u64) -> crate::io::Result<()>{
+        self.fs.set_stream_len(self.objid, self.id, len)?;
+        self.listing = self.fs.get_stream_by_id(self.objid, self.id)?;
+        Ok(())
+    }
+}
+
+impl<S: Read + Seek> Read for StreamHandle<'_, S>{
+    fn read(&mut self, out: &mut [u8]) -> crate::io::Result<usize>{
+        let n = self.fs.read_from_stream(out, self.pos, &self.listing)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<S> Seek for StreamHandle<'_, S>{
+    fn seek(&mut self, pos: SeekPos) -> crate::io::Result<crate::io::VolLocation>{
+        let size = self.listing.size as i128;
+        let pos = match pos{
+            SeekPos::Start(n) => Some(n as i128),
+            SeekPos::StartSector(n) => i128::try_from(n).ok().and_then(|n| n.checked_mul(1024)),
+            SeekPos::AbsPos(loc) => {
+                if loc.offset>=1024{
+                    return Err(crate::io::Error::InvalidInput);
+                }
+                i128::try_from(loc.sector).ok().and_then(|n| n.checked_mul(1024)).map(|n| n+(loc.offset as i128))
+            },
+            SeekPos::Curr(n) => Some((self.pos as i128)+(n as i128)),
+            SeekPos::End(n) => Some(size+(n as i128)),
+            SeekPos::EndSector(n) => n.checked_mul(1024).and_then(|n| n.checked_add(size)),
+        };
+
+        self.pos = pos.and_then(|pos| u64::try_from(pos).ok()).ok_or(crate::io::Error::InvalidInput)?;
+
+        Ok(crate::io::VolLocation{sector: (self.pos>>10) as u128, offset: self.pos&1023})
+    }
+}
+
+impl<S: Read + Write + Seek> Write for StreamHandle<'_, S>{
+    fn write(&mut self, buf: &[u8]) -> crate::io::Result<usize>{
+        if buf.is_empty(){
+            return Ok(0)
+        }
+
+        self.fs.write_stream_inner(self.objid, self.id, &mut self.listing, self.pos, buf)?;
+        self.pos += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> crate::io::Result<()>{
+        self.fs.stream.flush()
+    }
+}
+
+/// An entry of a directory, as returned by [`FilesystemAccess::read_dir`].
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct DirEntry{
+    pub name: String,
+    pub objid: ObjectId,
+    pub flags: DirectoryElementFlags,
+}
+
+/// An iterator over the entries of a directory. See [`FilesystemAccess::read_dir`].
+pub struct ReadDir<'a, S>{
+    fs: &'a mut FilesystemAccess<S>,
+    stream: StreamListing,
+    strings: Option<StreamListing>,
+    slot: u64,
+    skip_hidden: bool,
+}
+
+impl<S> ReadDir<'_, S>{
+    /// Causes the iterator to skip entries with the `HIDDEN` flag.
+    pub fn skip_hidden(mut self) -> Self{
+        self.skip_hidden = true;
+        self
+    }
+}
+
+impl<S: Read + Seek> ReadDir<'_, S>{
+    fn read_entry(&mut self, element: &DirectoryElement) -> crate::io::Result<String>{
+        if let Some(nameref) = element.name_index{
+            let strings = self.strings.as_ref().ok_or(crate::io::Error::InvalidData)?;
+            self.fs.read_nullstr_from_stream(nameref.get(), strings)
+        }else{
+            let name = element.name.split(|f|*f==0).next().unwrap();
+            core::str::from_utf8(name).map(String::from).map_err(|_| crate::io::Error::InvalidData)
+        }
+    }
+}
+
+impl<S: Read + Seek> Iterator for ReadDir<'_, S>{
+    type Item = crate::io::Result<DirEntry>;
+
+    fn next(&mut self) -> Option<Self::Item>{
+        let len = self.stream.size/(size_of::<DirectoryElement>() as u64);
+        while self.slot<len{
+            let mut element: DirectoryElement = Zeroable::zeroed();
+
+            let pos = self.slot*(size_of::<DirectoryElement>() as u64);
+            self.slot += 1;
+
+            if let Err(e) = self.fs.read_fully_from_stream(bytemuck::bytes_of_mut(&mut element), pos, &self.stream){
+                return Some(Err(e))
+            }
+
+            let objid = match element.objidx{
+                Some(objid) => objid,
+                None => continue,
+            };
+
+            if self.skip_hidden && element.flags.contains(DirectoryElementFlags::HIDDEN){
+                continue;
+            }
+
+            return Some(self.read_entry(&element).map(|name| DirEntry{name, objid, flags: element.flags}));
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use super::*;
+
+    /// An in-memory volume, which grows as it is written to.
+    struct MemVolume{
+        data: Vec<u8>,
+        pos: usize,
+    }
+
+    impl Read for MemVolume{
+        fn read(&mut self, out: &mut [u8]) -> crate::io::Result<usize>{
+            let len = out.len().min(self.data.len().saturating_sub(self.pos));
+            out[..len].copy_from_slice(&self.data[self.pos..][..len]);
+            self.pos += len;
+            Ok(len)
+        }
+    }
+
+    impl Write for MemVolume{
+        fn write(&mut self, buf: &[u8]) -> crate::io::Result<usize>{
+            let end = self.pos+buf.len();
+            if end>self.data.len(){
+                self.data.resize(end, 0);
+            }
+            self.data[self.pos..end].copy_from_slice(buf);
+            self.pos = end;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> crate::io::Result<()>{
+            Ok(())
+        }
+    }
+
+    impl Seek for MemVolume{
+        fn seek(&mut self, pos: SeekPos) -> crate::io::Result<crate::io::VolLocation>{
+            let pos = match pos{
+                SeekPos::Start(n) => n as i128,
+                SeekPos::StartSector(n) => (n as i128)*1024,
+                SeekPos::AbsPos(loc) => (loc.sector as i128)*1024+(loc.offset as i128),
+                SeekPos::Curr(n) => (self.pos as i128)+(n as i128),
+                SeekPos::End(n) => (self.data.len() as i128)+(n as i128),
+                SeekPos::EndSector(n) => (self.data.len() as i128)+n*1024,
+            };
+            self.pos = usize::try_from(pos).map_err(|_| crate::io::Error::InvalidInput)?;
+            Ok(crate::io::VolLocation{sector: (self.pos>>10) as u128, offset: (self.pos&1023) as u64})
+        }
+    }
+
+    const OWNER: Uuid = Uuid{lo: 1, hi: 0};
+
+    /// Returns a zeroed in-memory volume of `sectors` sectors, which has not been formatted.
+    fn volume(sectors: usize) -> FilesystemAccess<MemVolume>{
+        FilesystemAccess::new(MemVolume{data: alloc::vec![0; sectors<<10], pos: 0})
+    }
+
+    fn format(sectors: u128, options: FormatOptions) -> (FilesystemAccess<MemVolume>, ObjectId){
+        let mut fs = volume(sectors as usize);
+        fs.format("test", Uuid{lo: 2, hi: 3}, sectors, options).unwrap();
+        let root = fs.get_or_read_descriptor().unwrap().root_object_id.unwrap();
+        (fs, root)
+    }
+
+    /// Creates `count` regular files, and writes `chunks` sectors to each of them in turn, so that the content of every file is fragmented.
+    fn create_interleaved(fs: &mut FilesystemAccess<MemVolume>, count: u8, chunks: u64) -> Vec<ObjectId>{
+        let files: Vec<ObjectId> = (0..count).map(|_| fs.create_object(0, ObjectType::RegularFile, "", OWNER).unwrap()).collect();
+
+        for i in 0..chunks{
+            for (n, &file) in files.iter().enumerate(){
+                fs.write_to_stream(file, StreamId(3), i*1024, &[(n as u8)+1; 1024]).unwrap();
+            }
+        }
+
+        files
+    }
+
+    fn reserved_sectors(fs: &mut FilesystemAccess<MemVolume>) -> u64{
+        fs.read_alloc_table().unwrap().iter().map(|span| span.extent).sum()
+    }
+
+    /// Options with an allocation table large enough that it is never grown, so that the number of reserved sectors only depends on the objects on the volume.
+    fn large_alloc_table() -> FormatOptions{
+        FormatOptions::new().alloc_tab_size(8192).boot_sectors(16)
+    }
+
+    fn read_all(fs: &mut FilesystemAccess<MemVolume>, objid: ObjectId, id: StreamId) -> Vec<u8>{
+        let listing = fs.get_stream_by_id(objid, id).unwrap();
+        let mut buf = alloc::vec![0u8; listing.size as usize];
+        fs.read_fully_from_stream(&mut buf, 0, &listing).unwrap();
+        buf
+    }
+
+    #[test]
+    fn allocate_first_fit(){
+        let mut fs = volume(64);
+        fs.create_filesystem("test", Uuid{lo: 2, hi: 3}, 64).unwrap();
+        let baseline = reserved_sectors(&mut fs);
+        let spans = |fs: &mut FilesystemAccess<MemVolume>| fs.read_alloc_table().unwrap().iter().filter(|span| span.extent!=0).count();
+
+        let first = fs.allocate_contiguous_space(1).unwrap();
+        let used = spans(&mut fs);
+        let second = fs.allocate_contiguous_space(2048).unwrap();
+
+        assert_eq!(second, SectorPos(first.0+1));
+        assert_eq!(spans(&mut fs), used);
+        assert_eq!(reserved_sectors(&mut fs), baseline+3);
+
+        assert_eq!(fs.allocate_contiguous_space(0), Err(crate::io::Error::InvalidInput));
+        assert_eq!(fs.allocate_contiguous_space(64<<10), Err(crate::io::Error::NoSpace));
+    }
+
+    #[test]
+    fn free_space_splits_and_merges(){
+        let mut fs = volume(64);
+        fs.create_filesystem("test", Uuid{lo: 2, hi: 3}, 64).unwrap();
+        let baseline = reserved_sectors(&mut fs);
+
+        let base = fs.allocate_contiguous_space(3<<10).unwrap().0;
+        fs.free_space(SectorPos(base+1), 1).unwrap();
+        assert_eq!(reserved_sectors(&mut fs), baseline+2);
+        assert_eq!(fs.free_space(SectorPos(base+1), 1), Err(crate::io::Error::DoubleFree));
+        assert_eq!(fs.free_space(SectorPos(base), 2), Err(crate::io::Error::DoubleFree));
+
+        assert_eq!(fs.allocate_contiguous_space(1024), Ok(SectorPos(base+1)));
+        fs.free_space(SectorPos(base), 3).unwrap();
+        assert_eq!(reserved_sectors(&mut fs), baseline);
+    }
+
+    #[test]
+    fn grow_object_table_keeps_ids(){
+        let mut fs = volume(64);
+        fs.create_filesystem("test", Uuid{lo: 2, hi: 3}, 64).unwrap();
+        let baseline = reserved_sectors(&mut fs);
+
+        // Place an object in the last slot of the table, which is the lowest entry
+        let objid = ObjectId(NonZeroU64::new(16).unwrap());
+        let obj = Object{strong_ref: 1, weak_ref: 1, ..Zeroable::zeroed()};
+        fs.stream.seek(SeekPos::Start(((64<<10)-16*size_of::<Object>()) as u64)).unwrap();
+        fs.stream.write_all(bytemuck::bytes_of(&obj)).unwrap();
+
+        fs.grow_object_table().unwrap();
+        let desc = *fs.get_or_read_descriptor().unwrap();
+        assert_eq!((desc.objtab_end, desc.objtab_size), (SectorPos(64), 2048));
+        assert_eq!(reserved_sectors(&mut fs), baseline+1);
+        assert_eq!(fs.get_obj_by_id(objid), Ok(obj));
+
+        assert_eq!(fs.try_reserve_at(SectorPos(61), 1), Ok(true));
+        fs.grow_object_table().unwrap();
+        let desc = *fs.get_or_read_descriptor().unwrap();
+        assert_ne!(desc.objtab_end, SectorPos(64));
+        assert_eq!(desc.objtab_size, 4096);
+        assert_eq!(reserved_sectors(&mut fs), baseline+4);
+        assert_eq!(fs.get_obj_by_id(objid), Ok(obj));
+    }
+
+    #[test]
+    fn create_object_streams(){
+        let mut fs = volume(64);
+        fs.create_filesystem("test", Uuid{lo: 2, hi: 3}, 64).unwrap();
+
+        let file = fs.create_object(100, ObjectType::RegularFile, "name\0", OWNER).unwrap();
+        let obj = fs.get_obj_by_id(file).unwrap();
+        assert_eq!((obj.ty, obj.strong_ref, obj.weak_ref), (ObjectType::RegularFile, 1, 1));
+
+        let (id, listing) = fs.find_stream_by_id(file, consts::FILEDATA_STREAM).unwrap();
+        assert_eq!((id, listing.size), (StreamId(3), 100));
+
+        let strings = fs.get_stream_by_id(file, StreamId(1)).unwrap();
+        let mut buf = [0u8; 6];
+        fs.read_fully_from_stream(&mut buf, 0, &strings).unwrap();
+        assert_eq!(&buf, b"\0name\0");
+
+        let security = fs.get_stream_by_id(file, StreamId(2)).unwrap();
+        let mut row = SecurityDescriptorRow::zeroed();
+        fs.read_fully_from_stream(bytemuck::bytes_of_mut(&mut row), 0, &security).unwrap();
+        assert_eq!(row.principal, u128::from(OWNER));
+        assert_eq!(row.permission_name, extend_str::<24>(consts::OBJECTOWNER_PERMISSION));
+    }
+
+    #[test]
+    fn reopen_checks_descriptor_crc(){
+        let mut fs = volume(64);
+        fs.create_filesystem("test", Uuid{lo: 2, hi: 3}, 64).unwrap();
+        fs.sync().unwrap();
+        let mut data = fs.stream.data;
+
+        let mut fs = FilesystemAccess::new(MemVolume{data: data.clone(), pos: 0});
+        assert_eq!(fs.get_or_read_descriptor().map(|desc| (desc.volume_id_lo, desc.volume_id_hi)), Ok((2, 3)));
+
+        data[1024+16] ^= 1;
+        let mut fs = FilesystemAccess::new(MemVolume{data, pos: 0});
+        assert_eq!(fs.get_or_read_descriptor().map(|_| ()), Err(crate::io::Error::InvalidData));
+    }
+
+    #[test]
+    fn create_filesystem_has_empty_root(){
+        let mut fs = volume(64);
+        fs.create_filesystem("test", Uuid{lo: 2, hi: 3}, 64).unwrap();
+
+        let mut fs = FilesystemAccess::new(MemVolume{data: fs.stream.data, pos: 0});
+        let root = fs.get_or_read_descriptor().unwrap().root_object_id.unwrap();
+        assert_eq!(fs.get_obj_by_id(root).unwrap().ty, ObjectType::Directory);
+        assert_eq!(fs.search_directory(root, "file"), Err(crate::io::Error::NotFound));
+        assert_eq!(fs.try_reserve_at(SectorPos(63), 1), Ok(false));
+    }
+
+    #[test]
+    fn format_with_options(){
+        let options = FormatOptions::new().objtab_size(4096).alloc_tab_begin(AbsPos(16<<10)).alloc_tab_size(2048).boot_sectors(12);
+        let (mut fs, root) = format(256, options);
+
+        let desc = *fs.get_or_read_descriptor().unwrap();
+        assert_eq!((desc.objtab_end, desc.objtab_size), (SectorPos(256), 4096));
+        assert_eq!((desc.alloc_tab_begin, desc.alloc_tab_size), (AbsPos(16<<10), 2048));
+        assert_eq!(fs.get_obj_by_id(root).unwrap().ty, ObjectType::Directory);
+
+        for sector in [11, 16, 17, 252, 255]{
+            assert_eq!(fs.try_reserve_at(SectorPos(sector), 1), Ok(false));
+        }
+
+        let mut fs = volume(256);
+        for options in [
+            FormatOptions::new().boot_sectors(1),
+            FormatOptions::new().objtab_size(100),
+            FormatOptions::new().alloc_tab_begin(AbsPos(1024)),
+            FormatOptions::new().objtab_size(256<<10),
+        ]{
+            assert_eq!(fs.format("test", Uuid{lo: 2, hi: 3}, 256, options), Err(crate::io::Error::InvalidInput));
+        }
+    }
+
+    #[test]
+    fn long_labels(){
+        let label = "a volume label which is too long to be stored inline";
+        let (mut fs, _) = format(256, FormatOptions::new());
+        assert_eq!(fs.label().unwrap(), "test");
+
+        fs.set_label(label).unwrap();
+        fs.sync().unwrap();
+
+        let mut fs = FilesystemAccess::new(MemVolume{data: fs.stream.data, pos: 0});
+        assert_eq!(fs.label().unwrap(), label);
+        assert_eq!(&fs.get_or_read_descriptor().unwrap().label[..], &label.as_bytes()[..32]);
+
+        for bad in ["short\0", "a volume label which is too long\0to be stored inline"]{
+            assert_eq!(fs.set_label(bad), Err(crate::io::Error::InvalidInput));
+        }
+        assert_eq!(fs.label().unwrap(), label);
+
+        fs.set_label("short").unwrap();
+        assert_eq!(fs.label().unwrap(), "short");
+        assert_eq!(fs.get_or_read_descriptor().unwrap().label_ref, None);
+    }
+
+    #[test]
+    fn stream_handle_read_write(){
+        let (mut fs, _) = format(256, FormatOptions::new());
+
+        let file = fs.create_object(100, ObjectType::RegularFile, "", OWNER).unwrap();
+        let mut handle = fs.open_stream(file, consts::FILEDATA_STREAM).unwrap();
+        handle.seek(SeekPos::Start(40)).unwrap();
+        handle.write_all(b"hello").unwrap();
+
+        assert_eq!(handle.seek(SeekPos::End(-60)), Ok(crate::io::VolLocation{sector: 0, offset: 40}));
+        let mut buf = [1u8; 6];
+        handle.read_fully(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello\0");
+        assert_eq!(handle.listing().size, 100);
+
+        handle.seek(SeekPos::End(100)).unwrap();
+        assert_eq!(handle.write(&[]), Ok(0));
+        assert_eq!(handle.listing().size, 100);
+        assert_eq!(fs.get_stream_by_id(file, StreamId(3)).unwrap().size, 100);
+    }
+
+    #[test]
+    fn write_past_end_fills_zeroes(){
+        let (mut fs, _) = format(256, FormatOptions::new());
+
+        let inline = fs.create_object(0, ObjectType::RegularFile, "", OWNER).unwrap();
+        fs.write_to_stream(inline, StreamId(3), 10, b"end").unwrap();
+        assert_eq!(read_all(&mut fs, inline, StreamId(3)), [&[0; 10][..], &b"end"[..]].concat());
+
+        let file = fs.create_object(100, ObjectType::RegularFile, "", OWNER).unwrap();
+        fs.write_to_stream(file, StreamId(3), 0, &[1; 100]).unwrap();
+        fs.write_to_stream(file, StreamId(3), 1000, b"end").unwrap();
+
+        let content = read_all(&mut fs, file, StreamId(3));
+        assert_eq!(content.len(), 1003);
+        assert!(content[..100].iter().all(|b| *b==1));
+        assert!(content[100..1000].iter().all(|b| *b==0));
+        assert_eq!(&content[1000..], b"end");
+    }
+
+    #[test]
+    fn promote_interleaved_writes(){
+        let (mut fs, _) = format(1024, large_alloc_table());
+
+        let files = create_interleaved(&mut fs, 3, 100);
+
+        for (n, &file) in files.iter().enumerate(){
+            let listing = fs.get_stream_by_id(file, StreamId(3)).unwrap();
+            assert_eq!(listing.size, 100*1024);
+            assert!(listing.flags.get_indirection()>=3);
+            assert!(read_all(&mut fs, file, StreamId(3)).iter().all(|b| *b==(n as u8)+1));
+        }
+
+        let file = fs.create_object(0, ObjectType::RegularFile, "", OWNER).unwrap();
+        fs.write_to_stream(file, StreamId(3), 0, &[7; 32]).unwrap();
+        assert_eq!(fs.get_stream_by_id(file, StreamId(3)).unwrap().flags.get_indirection(), 0);
+        fs.write_to_stream(file, StreamId(3), 32, &[7; 1]).unwrap();
+        assert_eq!(fs.get_stream_by_id(file, StreamId(3)).unwrap().flags.get_indirection(), 1);
+        assert_eq!(read_all(&mut fs, file, StreamId(3)), [7; 33]);
+    }
+
+    #[test]
+    fn truncate_and_demote(){
+        let (mut fs, _) = format(1024, large_alloc_table());
+        let files = create_interleaved(&mut fs, 2, 100);
+        let reserved = reserved_sectors(&mut fs);
+
+        fs.set_stream_len(files[0], StreamId(3), 5000).unwrap();
+        let listing = fs.get_stream_by_id(files[0], StreamId(3)).unwrap();
+        assert_eq!(listing.size, 5000);
+        assert!(read_all(&mut fs, files[0], StreamId(3)).iter().all(|b| *b==1));
+        assert!(reserved_sectors(&mut fs)<=reserved-95);
+
+        fs.set_stream_len(files[0], StreamId(3), 1000).unwrap();
+        assert_eq!(fs.get_stream_by_id(files[0], StreamId(3)).unwrap().flags.get_indirection(), 1);
+
+        fs.set_stream_len(files[0], StreamId(3), 10).unwrap();
+        let listing = fs.get_stream_by_id(files[0], StreamId(3)).unwrap();
+        assert_eq!(listing.flags.get_indirection(), 0);
+        assert_eq!(read_all(&mut fs, files[0], StreamId(3)), [1; 10]);
+
+        fs.set_stream_len(files[0], StreamId(3), 20).unwrap();
+        assert_eq!(read_all(&mut fs, files[0], StreamId(3)), [[1; 10], [0; 10]].concat());
+
+        fs.set_stream_len(files[1], StreamId(3), 0).unwrap();
+        assert_eq!(fs.truncate_stream(files[1], StreamId(3), 1), Err(crate::io::Error::InvalidInput));
+        assert_eq!(read_all(&mut fs, files[1], StreamId(3)), []);
+    }
+
+    #[test]
+    fn release_reclaims_space_and_slots(){
+        let (mut fs, _) = format(1024, large_alloc_table());
+        let baseline = reserved_sectors(&mut fs);
+
+        let files = create_interleaved(&mut fs, 2, 50);
+        fs.set_stream_len(files[1], StreamId(3), 5000).unwrap();
+        for &file in &files{
+            fs.release_strong(file).unwrap();
+        }
+
+        assert_eq!(reserved_sectors(&mut fs), baseline);
+        assert_eq!(fs.get_obj_by_id(files[1]), Err(crate::io::Error::NotFound));
+        assert_eq!(fs.release_strong(files[1]), Err(crate::io::Error::NotFound));
+
+        let reused = fs.create_object(0, ObjectType::RegularFile, "", OWNER).unwrap();
+        assert_eq!(reused, files[0]);
+    }
+
+    #[test]
+    fn link_acquires_references(){
+        let (mut fs, root) = format(256, FormatOptions::new());
+        let long = "an entry with a name which is longer than forty bytes";
+
+        let file = fs.create_object(0, ObjectType::RegularFile, "", OWNER).unwrap();
+        fs.link(root, "file", file, DirectoryElementFlags::empty()).unwrap();
+        fs.link(root, long, file, DirectoryElementFlags::WEAK).unwrap();
+
+        assert_eq!(fs.search_directory(root, "file"), Ok(file));
+        assert_eq!(fs.search_directory(root, long), Ok(file));
+
+        assert_eq!(fs.link(root, "file", file, DirectoryElementFlags::empty()), Err(crate::io::Error::AlreadyExists));
+        assert_eq!(fs.link(file, "file", root, DirectoryElementFlags::empty()), Err(crate::io::Error::InvalidInput));
+
+        let file = fs.get_obj_by_id(file).unwrap();
+        assert_eq!((file.strong_ref, file.weak_ref), (2, 2));
+        let root = fs.get_obj_by_id(root).unwrap();
+        assert_eq!((root.strong_ref, root.weak_ref), (1, 1));
+    }
+
+    #[test]
+    fn rename_and_unlink_refcounts(){
+        let (mut fs, root) = format(256, FormatOptions::new());
+        let refs = |fs: &mut FilesystemAccess<MemVolume>, objid| fs.get_obj_by_id(objid).map(|obj| (obj.strong_ref, obj.weak_ref));
+
+        let a = fs.create_object(0, ObjectType::RegularFile, "", OWNER).unwrap();
+        let b = fs.create_object(0, ObjectType::RegularFile, "", OWNER).unwrap();
+        let dir = fs.create_object(0, ObjectType::Directory, "", OWNER).unwrap();
+        fs.link(root, "a", a, DirectoryElementFlags::empty()).unwrap();
+        fs.link(root, "a2", a, DirectoryElementFlags::empty()).unwrap();
+        fs.link(root, "b", b, DirectoryElementFlags::empty()).unwrap();
+        fs.link(root, "dir", dir, DirectoryElementFlags::empty()).unwrap();
+        for objid in [a, b, dir]{
+            fs.release_strong(objid).unwrap();
+        }
+        assert_eq!(refs(&mut fs, a), Ok((2, 1)));
+
+        let long = "a renamed entry with a name longer than forty bytes";
+        fs.rename(root, "a", root, long).unwrap();
+        assert_eq!(fs.search_directory(root, "a"), Err(crate::io::Error::NotFound));
+        assert_eq!(fs.search_directory(root, long), Ok(a));
+        assert_eq!(refs(&mut fs, a), Ok((2, 1)));
+
+        fs.rename(root, long, root, "b").unwrap();
+        assert_eq!(fs.search_directory(root, "b"), Ok(a));
+        assert_eq!(refs(&mut fs, a), Ok((2, 1)));
+        assert_eq!(refs(&mut fs, b), Err(crate::io::Error::NotFound));
+
+        fs.rename(root, "b", dir, "moved").unwrap();
+        assert_eq!(fs.search_directory(root, "b"), Err(crate::io::Error::NotFound));
+        assert_eq!(fs.search_directory(dir, "moved"), Ok(a));
+        assert_eq!(refs(&mut fs, a), Ok((2, 1)));
+
+        fs.unlink(dir, "moved").unwrap();
+        assert_eq!(refs(&mut fs, a), Ok((1, 1)));
+        assert_eq!(fs.unlink(dir, "moved"), Err(crate::io::Error::NotFound));
+        fs.unlink(root, "a2").unwrap();
+        assert_eq!(refs(&mut fs, a), Err(crate::io::Error::NotFound));
+        assert_eq!(fs.search_directory(root, "dir"), Ok(dir));
+    }
+
+    #[test]
+    fn release_keeps_slot_while_weakly_referenced(){
+        let (mut fs, root) = format(256, FormatOptions::new());
+
+        let file = fs.create_object(0, ObjectType::RegularFile, "", OWNER).unwrap();
+        fs.link(root, "weak", file, DirectoryElementFlags::WEAK).unwrap();
+        fs.release_strong(file).unwrap();
+
+        let obj = fs.get_obj_by_id(file).unwrap();
+        assert_eq!((obj.strong_ref, obj.weak_ref), (0, 1));
+        assert_eq!(fs.get_stream_by_id(file, StreamId(3)), Err(crate::io::Error::NotFound));
+        assert_eq!(fs.release_strong(file), Err(crate::io::Error::InvalidInput));
+
+        fs.unlink(root, "weak").unwrap();
+        assert_eq!(fs.get_obj_by_id(file), Err(crate::io::Error::NotFound));
+    }
+
+    #[test]
+    fn read_dir_skips_hidden(){
+        let (mut fs, root) = format(256, FormatOptions::new());
+        let long = "an entry with a name which is longer than forty bytes";
+
+        let file = fs.create_object(0, ObjectType::RegularFile, "", OWNER).unwrap();
+        fs.link(root, "visible", file, DirectoryElementFlags::empty()).unwrap();
+        fs.link(root, "hidden", file, DirectoryElementFlags::HIDDEN).unwrap();
+        fs.link(root, long, file, DirectoryElementFlags::empty()).unwrap();
+        fs.unlink(root, "visible").unwrap();
+
+        let entries: Vec<DirEntry> = fs.read_dir(root).unwrap().map(Result::unwrap).collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0], DirEntry{name: String::from("hidden"), objid: file, flags: DirectoryElementFlags::HIDDEN});
+
+        let names: Vec<String> = fs.read_dir(root).unwrap().skip_hidden().map(|entry| entry.unwrap().name).collect();
+        assert_eq!(names, [long]);
+        assert_eq!(fs.read_dir(file).map(|_| ()), Err(crate::io::Error::InvalidInput));
+    }
+
+    #[test]
+    fn resolve_absolute_paths(){
+        let (mut fs, root) = format(256, FormatOptions::new());
+
+        let a = fs.create_object(0, ObjectType::Directory, "", OWNER).unwrap();
+        fs.link(root, "a", a, DirectoryElementFlags::empty()).unwrap();
+        let b = fs.create_object(0, ObjectType::Directory, "", OWNER).unwrap();
+        fs.link(a, "b", b, DirectoryElementFlags::empty()).unwrap();
+        let file = fs.create_object(0, ObjectType::RegularFile, "", OWNER).unwrap();
+        fs.link(root, "file", file, DirectoryElementFlags::empty()).unwrap();
+
+        assert_eq!(fs.resolve_path("/"), Ok(root));
+        assert_eq!(fs.resolve_path("//a/./b//"), Ok(b));
+        assert_eq!(fs.resolve_path("/a/b/../../file"), Ok(file));
+        assert_eq!(fs.resolve_path("/../a"), Ok(a));
+        assert_eq!(fs.resolve_path("/a/missing"), Err(crate::io::Error::NotFound));
+    }
+
+    #[test]
+    fn symlink_loops(){
+        let (mut fs, root) = format(256, FormatOptions::new());
+
+        let dir = fs.create_object(0, ObjectType::Directory, "", OWNER).unwrap();
+        fs.link(root, "dir", dir, DirectoryElementFlags::empty()).unwrap();
+        let file = fs.create_object(0, ObjectType::RegularFile, "", OWNER).unwrap();
+        fs.link(root, "file", file, DirectoryElementFlags::empty()).unwrap();
+
+        let up = fs.create_symlink(dir, "up", "../file", OWNER).unwrap();
+        fs.create_symlink(root, "chain", "dir/up", OWNER).unwrap();
+        fs.create_symlink(root, "loop1", "loop2", OWNER).unwrap();
+        fs.create_symlink(root, "loop2", "/loop1", OWNER).unwrap();
+
+        assert_eq!(fs.read_link(up).as_deref(), Ok("../file"));
+        assert_eq!(fs.read_link(file), Err(crate::io::Error::InvalidInput));
+        assert_eq!(fs.resolve_path("/dir/up"), Ok(up));
+        assert_eq!(fs.resolve_path_follow(root, &[], "chain", 2), Ok(file));
+        assert_eq!(fs.resolve_path_follow(root, &[], "chain", 1), Err(crate::io::Error::SymlinkLoop));
+        assert_eq!(fs.resolve_path_follow(root, &[], "loop1", 40), Err(crate::io::Error::SymlinkLoop));
+    }
+
+    #[test]
+    fn device_nodes(){
+        let (mut fs, root) = format(256, FormatOptions::new());
+        let devid = DeviceId{devid_lo: 5, devid_hi: 6};
+        let legacy = LegacyDeviceNumber{major: 8, minor: 1};
+
+        let block = fs.create_device_node(root, "sda1", ObjectType::BlockDevice, devid, Some(legacy), OWNER).unwrap();
+        let chr = fs.create_device_node(root, "null", ObjectType::CharDevice, devid, None, OWNER).unwrap();
+
+        assert_eq!(fs.resolve_path("/sda1"), Ok(block));
+        assert_eq!(fs.get_obj_by_id(block).unwrap().ty, ObjectType::BlockDevice);
+        assert_eq!(fs.device_id(block), Ok(devid));
+        assert_eq!(fs.legacy_device_number(block), Ok(Some(legacy)));
+        assert_eq!(fs.legacy_device_number(chr), Ok(None));
+
+        assert_eq!(fs.create_device_node(root, "file", ObjectType::RegularFile, devid, None, OWNER), Err(crate::io::Error::InvalidInput));
+        assert_eq!(fs.create_device_node(root, "null", ObjectType::CharDevice, devid, None, OWNER), Err(crate::io::Error::AlreadyExists));
+    }
+
+    #[test]
+    fn special_files(){
+        let (mut fs, root) = format(256, FormatOptions::new());
+
+        let fifo = fs.create_special(root, "fifo", ObjectType::PosixFifo, OWNER).unwrap();
+        let socket = fs.create_special(root, "socket", ObjectType::UnixSocket, OWNER).unwrap();
+
+        assert_eq!(fs.resolve_path("/fifo"), Ok(fifo));
+        assert_eq!(fs.get_obj_by_id(fifo).unwrap().ty, ObjectType::PosixFifo);
+        assert_eq!(fs.get_obj_by_id(socket).unwrap().ty, ObjectType::UnixSocket);
+        assert_eq!(fs.find_stream_by_id(fifo, consts::FILEDATA_STREAM).map(|_| ()), Err(crate::io::Error::NotFound));
+
+        assert_eq!(fs.create_special(root, "dir", ObjectType::Directory, OWNER), Err(crate::io::Error::InvalidInput));
+    }
+
+    #[test]
+    fn check_access_rows(){
+        let (mut fs, _) = format(256, FormatOptions::new());
+        let owner: u128 = OWNER.into();
+
+        let file = fs.create_object(0, ObjectType::RegularFile, "", OWNER).unwrap();
+        let row = |principal, mode: SecurityDescRowMode, permission| SecurityDescriptorRow{
+            principal,
+            stream_id: StreamId::STREAMS,
+            flags_and_mode: mode.into(),
+            permission_name_ref: None,
+            permission_name: extend_str(permission),
+        };
+
+        let mut rows = [SecurityDescriptorRow::zeroed(); 4];
+        let security = fs.get_stream_by_id(file, StreamId(2)).unwrap();
+        fs.read_fully_from_stream(bytemuck::bytes_of_mut(&mut rows[0]), 0, &security).unwrap();
+        rows[1] = row(consts::DEFAULT_PRINCIPAL, SecurityDescRowMode::Permit, consts::READ_PERMISSION);
+        rows[2] = row(7, SecurityDescRowMode::Deny, consts::READ_PERMISSION);
+        rows[3] = row(8, SecurityDescRowMode::Forbid, consts::ALL_PERMISSIONS);
+        fs.write_to_stream(file, StreamId(2), 0, bytemuck::cast_slice(&rows)).unwrap();
+
+        assert_eq!(fs.check_access(file, owner, consts::OBJECTOWNER_PERMISSION, None), Ok(true));
+        assert_eq!(fs.check_access(file, owner, consts::READ_PERMISSION, None), Ok(true));
+        assert_eq!(fs.check_access(file, owner, consts::WRITE_PERMISSION, None), Ok(false));
+        assert_eq!(fs.check_access(file, 7, consts::READ_PERMISSION, None), Ok(false));
+        assert_eq!(fs.check_access(file, 8, consts::READ_PERMISSION, None), Ok(false));
+        assert_eq!(fs.check_access(file, 9, consts::READ_PERMISSION, Some(StreamId(3))), Ok(true));
+    }
+
+    #[test]
+    fn inherit_from_ancestors(){
+        let (mut fs, root) = format(256, FormatOptions::new());
+
+        let dir = fs.create_object(0, ObjectType::Directory, "", OWNER).unwrap();
+        fs.link(root, "dir", dir, DirectoryElementFlags::empty()).unwrap();
+        let file = fs.create_object(0, ObjectType::RegularFile, "", OWNER).unwrap();
+        fs.link(dir, "file", file, DirectoryElementFlags::empty()).unwrap();
+
+        let row = |mode: SecurityDescRowMode| SecurityDescriptorRow{
+            principal: 7,
+            stream_id: StreamId::STREAMS,
+            flags_and_mode: mode.into(),
+            permission_name_ref: None,
+            permission_name: extend_str(consts::READ_PERMISSION),
+        };
+
+        for (objid, mode) in [(file, SecurityDescRowMode::Inherit), (dir, SecurityDescRowMode::Inherit), (root, SecurityDescRowMode::Permit)]{
+            let len = fs.get_stream_by_id(objid, StreamId(2)).unwrap().size;
+            fs.write_to_stream(objid, StreamId(2), len, bytemuck::bytes_of(&row(mode))).unwrap();
+        }
+
+        assert_eq!(fs.check_access(file, 7, consts::READ_PERMISSION, None), Ok(false));
+        let report = fs.check_access_inherited(file, &[root, dir], 7, consts::READ_PERMISSION, None).unwrap();
+        assert!(report.granted);
+        assert_eq!(report.decided_by, Some((root, row(SecurityDescRowMode::Permit))));
+
+        let report = fs.check_access_inherited(file, &[dir], 7, consts::READ_PERMISSION, None).unwrap();
+        assert_eq!((report.granted, report.decided_by), (false, None));
+
+        let report = fs.check_access_path("/dir/file", 7, consts::READ_PERMISSION, None).unwrap();
+        assert!(report.granted);
+    }
+
+    #[test]
+    fn access_precedence(){
+        let (mut fs, root) = format(256, FormatOptions::new());
+        let owner: u128 = OWNER.into();
+
+        let file = fs.create_object(0, ObjectType::RegularFile, "", OWNER).unwrap();
+        let long = "APermissionNameLongerThan24Bytes";
+        fs.add_security_row(file, consts::DEFAULT_PRINCIPAL, StreamId::STREAMS, SecurityDescRowMode::Permit.into(), consts::READ_PERMISSION).unwrap();
+        fs.add_security_row(file, 7, StreamId::STREAMS, SecurityDescRowMode::Deny.into(), consts::READ_PERMISSION).unwrap();
+        fs.add_security_row(file, 8, StreamId::STREAMS, SecurityDescRowMode::Permit.into(), consts::WRITE_PERMISSION).unwrap();
+        fs.add_security_row(file, 8, StreamId::STREAMS, SecurityDescRowMode::Forbid.into(), consts::ALL_PERMISSIONS).unwrap();
+        fs.add_security_row(file, 9, StreamId(3), SecurityDescRowMode::Permit.into(), long).unwrap();
+
+        assert_eq!(fs.check_access(file, owner, consts::OBJECTOWNER_PERMISSION, None), Ok(true));
+        assert_eq!(fs.check_access(file, owner, consts::READ_PERMISSION, None), Ok(true));
+        assert_eq!(fs.check_access(file, owner, consts::WRITE_PERMISSION, None), Ok(false));
+        assert_eq!(fs.check_access(file, 7, consts::READ_PERMISSION, None), Ok(false));
+        assert_eq!(fs.check_access(file, 8, consts::WRITE_PERMISSION, None), Ok(false));
+        assert_eq!(fs.check_access(file, 9, long, None), Ok(false));
+        assert_eq!(fs.check_access(file, 9, long, Some(StreamId(3))), Ok(true));
+
+        assert_eq!(fs.remove_security_rows(file, 8, consts::ALL_PERMISSIONS), Ok(1));
+        assert_eq!(fs.check_access(file, 8, consts::WRITE_PERMISSION, None), Ok(true));
+        assert!(fs.list_security_rows(file).unwrap().iter().any(|row| row.permission==long));
+
+        fs.link(root, "file", file, DirectoryElementFlags::empty()).unwrap();
+        fs.add_security_row(file, consts::DEFAULT_PRINCIPAL, StreamId::STREAMS, SecurityDescRowMode::Inherit.into(), consts::EXECUTE_PERMISION).unwrap();
+        fs.add_security_row(root, 7, StreamId::STREAMS, SecurityDescRowMode::Permit.into(), consts::EXECUTE_PERMISION).unwrap();
+
+        assert_eq!(fs.check_access(file, 7, consts::EXECUTE_PERMISION, None), Ok(false));
+        let report = fs.check_access_path("/file", 7, consts::EXECUTE_PERMISION, None).unwrap();
+        assert!(report.granted);
+        assert_eq!(report.decided_by.map(|(objid, _)| objid), Some(root));
+
+        let report = fs.check_access_path("/file", 7, consts::READ_PERMISSION, None).unwrap();
+        assert!(!report.granted);
+        assert_eq!(report.decided_by.map(|(objid, row)| (objid, row.flags_and_mode.mode())), Some((file, SecurityDescRowMode::Deny)));
+    }
+
+    #[test]
+    fn posix_mode_round_trip(){
+        let (mut fs, _) = format(256, FormatOptions::new());
+
+        let file = fs.create_object(0, ObjectType::RegularFile, "", OWNER).unwrap();
+        assert_eq!(fs.get_posix_attrs(file), Ok(None));
+
+        for mode in [0o754, 0o640, 0o007, 0o777]{
+            let attrs = LegacySecurityDescriptor{sd_uid: 1000, sd_gid: 100, sd_mode: mode, __sd_reserved: [0; 6]};
+            fs.set_posix_attrs(file, attrs, 11, 12).unwrap();
+            assert_eq!(fs.get_posix_attrs(file), Ok(Some(attrs)));
+            assert_eq!(fs.posix_mode_from_rows(file, 11, 12), Ok(mode));
+        }
+
+        let attrs = LegacySecurityDescriptor{sd_uid: 1001, sd_gid: 101, sd_mode: 0o700, __sd_reserved: [0; 6]};
+        fs.set_posix_attrs(file, attrs, 11, 12).unwrap();
+        fs.set_posix_attrs(file, attrs, 21, 22).unwrap();
+        assert_eq!(fs.check_access(file, 11, consts::WRITE_PERMISSION, None), Ok(false));
+        assert_eq!(fs.check_access(file, 21, consts::WRITE_PERMISSION, None), Ok(true));
+        assert_eq!(fs.posix_mode_from_rows(file, 21, 22), Ok(0o700));
+        assert_eq!(fs.list_security_rows(file).map(|rows| rows.len()), Ok(7));
+    }
+
+    #[test]
+    fn intern_string_deduplicates(){
+        let (mut fs, _) = format(256, FormatOptions::new());
+
+        let file = fs.create_object(0, ObjectType::RegularFile, "first\0second", OWNER).unwrap();
+        let second = fs.intern_string(file, "second").unwrap();
+        let new = fs.intern_string(file, "third").unwrap();
+
+        assert_eq!(second.get(), 7);
+        assert_eq!(fs.intern_string(file, "third"), Ok(new));
+        assert_eq!(fs.get_string(file, new).as_deref(), Ok("third"));
+        assert_eq!(fs.intern_string(file, "nul\0"), Err(crate::io::Error::InvalidInput));
+    }
+
+    #[test]
+    fn free_more_fragments_than_alloc_table_slots(){
+        let (mut fs, _) = format(1024, FormatOptions::new());
+        let slots = fs.get_or_read_descriptor().unwrap().alloc_tab_size/(size_of::<VolumeSpan>() as u64);
+
+        let files = create_interleaved(&mut fs, 2, slots+8);
+
+        fs.release_strong(files[0]).unwrap();
+
+        let other = fs.create_object(0, ObjectType::RegularFile, "", OWNER).unwrap();
+        for i in 0..(slots+8){
+            fs.write_to_stream(other, StreamId(3), i*1024, &[0xFF; 1024]).unwrap();
+        }
+
+        let (_, listing) = fs.find_stream_by_id(files[1], consts::FILEDATA_STREAM).unwrap();
+        let mut buf = [0u8; 1024];
+        for i in 0..(slots+8){
+            fs.read_fully_from_stream(&mut buf, i*1024, &listing).unwrap();
+            assert!(buf.iter().all(|b| *b==2));
+        }
+    }
+
+    #[test]
+    fn truncate_fragmented_stream(){
+        let (mut fs, _) = format(4096, FormatOptions::new());
+
+        let files = create_interleaved(&mut fs, 2, 586);
+
+        fs.set_stream_len(files[0], StreamId(3), 300_000).unwrap();
+
+        let listing = fs.get_stream_by_id(files[0], StreamId(3)).unwrap();
+        assert_eq!(listing.size, 300_000);
+
+        let mut buf = alloc::vec![0u8; 300_000];
+        fs.read_fully_from_stream(&mut buf, 0, &listing).unwrap();
+        assert!(buf.iter().all(|b| *b==1));
+
+        fs.set_stream_len(files[0], StreamId(3), 20).unwrap();
+        let listing = fs.get_stream_by_id(files[0], StreamId(3)).unwrap();
+        assert_eq!(listing.flags.get_indirection(), 0);
+        assert_eq!(&listing.inline_data[..20], &[1; 20]);
+
+        let listing = fs.get_stream_by_id(files[1], StreamId(3)).unwrap();
+        let mut buf = alloc::vec![0u8; 586*1024];
+        fs.read_fully_from_stream(&mut buf, 0, &listing).unwrap();
+        assert!(buf.iter().all(|b| *b==2));
+    }
+
+    #[test]
+    fn failed_rename_keeps_refcounts(){
+        let (mut fs, root) = format(256, FormatOptions::new());
+
+        let file = fs.create_object(0, ObjectType::RegularFile, "", OWNER).unwrap();
+        fs.link(root, "file", file, DirectoryElementFlags::empty()).unwrap();
+
+        assert_eq!(fs.rename(root, "file", file, "moved"), Err(crate::io::Error::InvalidInput));
+        assert_eq!(fs.get_obj_by_id(file).unwrap().strong_ref, 2);
+
+        let dir = fs.create_object(0, ObjectType::Directory, "", OWNER).unwrap();
+        fs.link(root, "dir", dir, DirectoryElementFlags::empty()).unwrap();
+        fs.release_strong(dir).unwrap();
+
+        assert_eq!(fs.rename(root, "file", dir, ""), Err(crate::io::Error::InvalidInput));
+        assert_eq!(fs.get_obj_by_id(file).unwrap().strong_ref, 2);
+        assert_eq!(fs.search_directory(root, "file"), Ok(file));
+
+        fs.link(dir, "existing", file, DirectoryElementFlags::empty()).unwrap();
+        for name in ["inner", "existing"]{
+            assert_eq!(fs.rename(root, "dir", dir, name), Err(crate::io::Error::InvalidInput));
+            assert_eq!(fs.search_directory(root, "dir"), Ok(dir));
+        }
+        assert_eq!(fs.get_obj_by_id(dir).unwrap().strong_ref, 1);
+        assert_eq!(fs.get_obj_by_id(file).unwrap().strong_ref, 3);
+    }
+
+    #[test]
+    fn unlink_directory_releases_entries(){
+        let (mut fs, root) = format(256, FormatOptions::new());
+
+        let dir = fs.create_object(0, ObjectType::Directory, "", OWNER).unwrap();
+        fs.link(root, "d", dir, DirectoryElementFlags::empty()).unwrap();
+        fs.release_strong(dir).unwrap();
+
+        let file = fs.create_object(0, ObjectType::RegularFile, "", OWNER).unwrap();
+        fs.link(dir, "f", file, DirectoryElementFlags::empty()).unwrap();
+        fs.release_strong(file).unwrap();
+
+        let kept = fs.create_object(0, ObjectType::RegularFile, "", OWNER).unwrap();
+        fs.link(dir, "weak", kept, DirectoryElementFlags::WEAK).unwrap();
+
+        fs.unlink(root, "d").unwrap();
+
+        assert_eq!(fs.get_obj_by_id(dir), Err(crate::io::Error::NotFound));
+        assert_eq!(fs.get_obj_by_id(file), Err(crate::io::Error::NotFound));
+        let kept = fs.get_obj_by_id(kept).unwrap();
+        assert_eq!((kept.strong_ref, kept.weak_ref), (1, 1));
+    }
+
+    #[test]
+    fn resolve_parent_through_ancestors(){
+        let (mut fs, root) = format(256, FormatOptions::new());
+
+        let a = fs.create_object(0, ObjectType::Directory, "", OWNER).unwrap();
+        fs.link(root, "a", a, DirectoryElementFlags::empty()).unwrap();
+        let b = fs.create_object(0, ObjectType::Directory, "", OWNER).unwrap();
+        fs.link(a, "b", b, DirectoryElementFlags::empty()).unwrap();
+        let file = fs.create_object(0, ObjectType::RegularFile, "", OWNER).unwrap();
+        fs.link(root, "file", file, DirectoryElementFlags::empty()).unwrap();
+
+        assert_eq!(fs.resolve_path("//a/./b//"), Ok(b));
+        assert_eq!(fs.resolve_path("/a/b/../../file"), Ok(file));
+        assert_eq!(fs.resolve_path("/../a"), Ok(a));
+        assert_eq!(fs.resolve_path_from(b, &[root, a], "../../file"), Ok(file));
+        assert_eq!(fs.resolve_path_from(b, &[root, a], "../../../a/b"), Ok(b));
+        assert_eq!(fs.resolve_path_from(b, &[a], "../.."), Err(crate::io::Error::InvalidInput));
+        assert_eq!(fs.resolve_path_from(b, &[], "/file"), Ok(file));
+        assert_eq!(fs.resolve_path("/file/."), Err(crate::io::Error::InvalidInput));
+    }
+
+    #[test]
+    fn reject_dot_entry_names(){
+        let (mut fs, root) = format(256, FormatOptions::new());
+
+        let file = fs.create_object(0, ObjectType::RegularFile, "", OWNER).unwrap();
+        fs.link(root, "file", file, DirectoryElementFlags::empty()).unwrap();
+
+        for name in [".", "..", "", "a/b"]{
+            assert_eq!(fs.link(root, name, file, DirectoryElementFlags::empty()), Err(crate::io::Error::InvalidInput));
+            assert_eq!(fs.rename(root, "file", root, name), Err(crate::io::Error::InvalidInput));
+            assert_eq!(fs.create_special(root, name, ObjectType::PosixFifo, OWNER), Err(crate::io::Error::InvalidInput));
+        }
+
+        let file = fs.get_obj_by_id(file).unwrap();
+        assert_eq!((file.strong_ref, file.weak_ref), (2, 1));
+
+        assert_eq!(fs.link(root, "self", root, DirectoryElementFlags::empty()), Err(crate::io::Error::InvalidInput));
+        assert_eq!(fs.link(root, "self", root, DirectoryElementFlags::WEAK), Err(crate::io::Error::InvalidInput));
+        let root = fs.get_obj_by_id(root).unwrap();
+        assert_eq!((root.strong_ref, root.weak_ref), (1, 1));
+    }
+
+    #[test]
+    fn allocate_within_volume(){
+        let mut fs = FilesystemAccess::new(MemVolume{data: alloc::vec![0; 128<<10], pos: 0});
+        assert_eq!(fs.create_filesystem("test", Uuid{lo: 2, hi: 3}, 64), Err(crate::io::Error::InvalidInput));
+
+        let mut fs = FilesystemAccess::new(MemVolume{data: Vec::new(), pos: 0});
+        fs.create_filesystem("test", Uuid{lo: 2, hi: 3}, 64).unwrap();
+
+        while let Ok(SectorPos(base)) = fs.allocate_contiguous_space(1024){
+            assert!(base<63);
+        }
+        assert_eq!(fs.allocate_contiguous_space(1024), Err(crate::io::Error::NoSpace));
+    }
+
+    #[test]
+    fn relocated_alloc_table_is_released(){
+        let (mut fs, _) = format(1024, FormatOptions::new());
+        let baseline = reserved_sectors(&mut fs);
+
+        // Reserving the sector after the table each time forces every growth to move it
+        for blocked in 0..4{
+            fs.grow_alloc_table().unwrap();
+            let (begin, end) = fs.alloc_table_sectors().unwrap();
+            assert!(begin>=8);
+            assert_eq!(reserved_sectors(&mut fs), baseline+((end-begin) as u64)+blocked);
+            assert_eq!(fs.try_reserve_at(SectorPos(end), 1), Ok(true));
+        }
+
+        let (mut fs, _) = format(1024, FormatOptions::new());
+
+        let files = create_interleaved(&mut fs, 2, 200);
+        for file in files{
+            fs.release_strong(file).unwrap();
+        }
+
+        let (begin, end) = fs.alloc_table_sectors().unwrap();
+        assert!(fs.get_or_read_descriptor().unwrap().alloc_tab_size>=4096);
+        assert_eq!(reserved_sectors(&mut fs), baseline+((end-begin) as u64));
+    }
+}
@@ -8,7 +8,7 @@ pub const fn extend_str<const N: usize>(s: &str) -> [u8;N]{
 
     let mut i = 0;
 
-    while i<N{
+    while i<s.len(){
         val[i] = s.as_bytes()[i];
         i+=1;
     }
@@ -9,6 +9,10 @@ pub enum Error{
     InvalidInput,
     InvalidData,
     NotFound,
+    NoSpace,
+    DoubleFree,
+    AlreadyExists,
+    SymlinkLoop,
 }
 
 impl core::fmt::Display for Error{
@@ -21,6 +25,10 @@ impl core::fmt::Display for Error{
             Error::InvalidInput => f.write_str("Invalid Input"),
             Error::InvalidData => f.write_str("Invalid Data"),
             Error::NotFound => f.write_str("Object or stream not found"),
+            Error::NoSpace => f.write_str("No space left on volume"),
+            Error::DoubleFree => f.write_str("Attempted to free space which is not allocated"),
+            Error::AlreadyExists => f.write_str("Object or directory entry already exists"),
+            Error::SymlinkLoop => f.write_str("Too many levels of symbolic links"),
         }
     }
 }
@@ -35,6 +43,7 @@ impl From<std::io::Error> for Error{
             std::io::ErrorKind::InvalidInput => Error::InvalidInput,
             std::io::ErrorKind::InvalidData => Error::InvalidData,
             std::io::ErrorKind::NotFound => Error::NotFound,
+            std::io::ErrorKind::AlreadyExists => Error::AlreadyExists,
             _ => Error::Unknown,
         }
     }
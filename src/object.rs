@@ -1,8 +1,12 @@
 
 use core::num::NonZeroU64;
 
+use alloc::vec::Vec;
+
 use bytemuck::{Pod,Zeroable, PodInOption, ZeroableInOption, TransparentWrapper};
 
+use crate::helpers::extend_str;
+
 #[repr(transparent)]
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, TransparentWrapper)]
 pub struct ObjectId(pub NonZeroU64);
@@ -223,6 +227,50 @@ pub struct LegacySecurityDescriptor{
     pub __sd_reserved: [u8;6]
 }
 
+impl LegacySecurityDescriptor{
+    /// The bits of `sd_mode` which correspond to [`SecurityDescriptorRow`]s.
+    pub const PERMISSION_BITS: u16 = 0o777;
+
+    /// The implementation bit set in the flags of every row returned by [`LegacySecurityDescriptor::synthesize_rows`], which identifies the rows to replace when the mode or owner changes.
+    pub const SYNTHESIZED_ROW: SecurityDescRowFlags = SecurityDescRowFlags::from_bits_truncate(0x0100000000000000);
+
+    /// Synthesizes the [`SecurityDescriptorRow`]s which correspond to the permission bits of `sd_mode`, for `owner` as the principal of `sd_uid` and `group` as the principal of `sd_gid`.
+    ///
+    /// Each of the owner, group, and other classes has a Read, Write, and Execute row for the whole object, for `owner`, `group`, and [`consts::DEFAULT_PRINCIPAL`] respectively.
+    /// The row is Permit if the corresponding bit is set. Otherwise, it is Deny for the owner and group classes, so that a permission granted to other principals is not granted to them,
+    ///  and it is omitted for the other class.
+    ///
+    /// Every row has [`LegacySecurityDescriptor::SYNTHESIZED_ROW`] set. The setuid, setgid, and sticky bits, and any other bits of `sd_mode`, have no corresponding rows.
+    pub fn synthesize_rows(&self, owner: u128, group: u128) -> Vec<SecurityDescriptorRow>{
+        let classes = [(owner, 6, true), (group, 3, true), (consts::DEFAULT_PRINCIPAL, 0, false)];
+        let permissions = [(consts::READ_PERMISSION, 4), (consts::WRITE_PERMISSION, 2), (consts::EXECUTE_PERMISION, 1)];
+
+        let mut rows = Vec::with_capacity(9);
+
+        for (principal, shift, deny) in classes{
+            for (permission, bit) in permissions{
+                let mode = if (self.sd_mode>>shift)&bit!=0{
+                    SecurityDescRowMode::Permit
+                }else if deny{
+                    SecurityDescRowMode::Deny
+                }else{
+                    continue
+                };
+
+                rows.push(SecurityDescriptorRow{
+                    principal,
+                    stream_id: StreamId::STREAMS,
+                    flags_and_mode: SecurityDescRowFlags::from(mode) | Self::SYNTHESIZED_ROW,
+                    permission_name_ref: None,
+                    permission_name: extend_str(permission),
+                });
+            }
+        }
+
+        rows
+    }
+}
+
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, Pod, Zeroable)]
 #[repr(C,align(4))]
 pub struct PhantomFSMagic([u8;4]);
@@ -8,4 +8,14 @@ pub struct Uuid{
     pub hi: u64,
 }
 
+impl From<Uuid> for u128{
+    fn from(id: Uuid) -> Self{
+        ((id.hi as u128)<<64) | (id.lo as u128)
+    }
+}
 
+impl From<u128> for Uuid{
+    fn from(id: u128) -> Self{
+        Self{lo: id as u64, hi: (id>>64) as u64}
+    }
+}